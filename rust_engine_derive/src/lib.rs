@@ -0,0 +1,57 @@
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Index, Member};
+
+//Bundle is implemented for tuples of up to this many components, the derive goes through them
+const MAX_FIELDS: usize = 8;
+
+//#[derive(Bundle)] for a struct whose fields are all components, inserted in field order
+#[proc_macro_derive(Bundle)]
+pub fn derive_bundle(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match bundle_impl(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn bundle_impl(input: &DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => return Err(Error::new(Span::call_site(), "Bundle can only be derived for structs")),
+    };
+    if fields.is_empty() {
+        return Err(Error::new(Span::call_site(), "Bundle can't be derived for a struct without fields"));
+    }
+    if fields.len() > MAX_FIELDS {
+        return Err(Error::new(Span::call_site(), format!("Bundle can only be derived for up to {} fields", MAX_FIELDS)));
+    }
+
+    let members: Vec<Member> = match fields {
+        Fields::Named(fields) => fields.named.iter().map(|field| Member::Named(field.ident.clone().unwrap())).collect(),
+        Fields::Unnamed(fields) => (0..fields.unnamed.len()).map(|i| Member::Unnamed(Index::from(i))).collect(),
+        Fields::Unit => unreachable!("checked above"),
+    };
+    let types: Vec<&syn::Type> = fields.iter().map(|field| &field.ty).collect();
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics crate::ecs::Bundle for #name #ty_generics #where_clause {
+            fn register(ecs: &mut crate::ecs::Ecs) -> Vec<std::any::TypeId> {
+                <(#(#types,)*) as crate::ecs::Bundle>::register(ecs)
+            }
+
+            fn on_add(&mut self, ecs: &crate::ecs::Ecs, handle: &crate::ecs::Handle) {
+                #(crate::ecs::Component::on_add(&mut self.#members, ecs, handle);)*
+            }
+
+            fn put(self, ecs: &mut crate::ecs::Ecs, handle: &crate::ecs::Handle, from: usize) {
+                crate::ecs::Bundle::put((#(self.#members,)*), ecs, handle, from)
+            }
+        }
+    })
+}
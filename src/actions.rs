@@ -0,0 +1,288 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use failure::Error;
+use serde::{Deserialize, Serialize};
+use winit::{MouseButton, VirtualKeyCode};
+use crate::input::Input;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Button {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton)
+}
+
+impl Button {
+    fn pressed(self, input : &Input) -> bool {
+        match self {
+            Button::Key(key) => input.keys.pressed(key),
+            Button::Mouse(button) => input.mouse_buttons.pressed(button)
+        }
+    }
+}
+
+//Analog inputs, in the units Input reports them in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Axis {
+    MouseMotionX,
+    MouseMotionY,
+    ScrollX,
+    ScrollY
+}
+
+impl Axis {
+    fn value(self, input : &Input) -> f32 {
+        match self {
+            Axis::MouseMotionX => input.mouse_motion().0 as f32,
+            Axis::MouseMotionY => input.mouse_motion().1 as f32,
+            Axis::ScrollX => input.scroll_lines().0,
+            Axis::ScrollY => input.scroll_lines().1
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Binding {
+    //1 while every button is held, so [LControl, S] is ctrl+s
+    Chord(Vec<Button>),
+    //-1 while negative is held, 1 while positive is, 0 with both. A and D make a move_x axis.
+    Buttons { negative : Button, positive : Button },
+    Axis { axis : Axis, scale : f32 }
+}
+
+impl Binding {
+    fn value(&self, input : &Input) -> f32 {
+        match self {
+            Binding::Chord(buttons) => {
+                if !buttons.is_empty() && buttons.iter().all(|button| button.pressed(input)) { 1.0 } else { 0.0 }
+            },
+            Binding::Buttons { negative, positive } => {
+                let side = |button : &Button| if button.pressed(input) { 1.0 } else { 0.0 };
+                side(positive) - side(negative)
+            },
+            Binding::Axis { axis, scale } => axis.value(input) * scale
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Action {
+    pub bindings : Vec<Binding>,
+    //values closer to 0 than this count as 0
+    #[serde(default)]
+    pub dead_zone : f32
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ActionState {
+    value : f32,
+    previous : f32
+}
+
+//Named actions like "jump" or "move_x" and the inputs bound to them, so game code doesn't ask for keys.
+//Kept in the Ecs as a resource, InputSystem updates it after the Input of a frame is complete.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ActionMap {
+    //ordered so saved files don't shuffle
+    actions : BTreeMap<String, Action>,
+    #[serde(skip)]
+    states : HashMap<String, ActionState>
+}
+
+impl ActionMap {
+    pub fn new() -> ActionMap {
+        ActionMap::default()
+    }
+
+    pub fn load(path : &str) -> Result<ActionMap, Error> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path : &str) -> Result<(), Error> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    //Adds to the bindings the action already has
+    pub fn bind(&mut self, action : &str, binding : Binding) {
+        self.actions.entry(action.to_string()).or_default().bindings.push(binding);
+    }
+
+    //Replaces all bindings of the action, e.g. from a controls menu
+    pub fn rebind(&mut self, action : &str, bindings : Vec<Binding>) {
+        self.actions.entry(action.to_string()).or_default().bindings = bindings;
+    }
+
+    pub fn set_dead_zone(&mut self, action : &str, dead_zone : f32) {
+        self.actions.entry(action.to_string()).or_default().dead_zone = dead_zone;
+    }
+
+    pub fn remove(&mut self, action : &str) -> Option<Action> {
+        self.states.remove(action);
+        self.actions.remove(action)
+    }
+
+    pub fn action(&self, action : &str) -> Option<&Action> {
+        self.actions.get(action)
+    }
+
+    //The binding with the largest value wins when several are active
+    pub fn update(&mut self, input : &Input) {
+        let states = &mut self.states;
+        for (name, action) in &self.actions {
+            let value = action.bindings.iter()
+                .map(|binding| binding.value(input))
+                .fold(0.0, |max : f32, value| if value.abs() > max.abs() { value } else { max });
+            let value = if value.abs() < action.dead_zone { 0.0 } else { value };
+
+            let state = states.entry(name.clone()).or_default();
+            state.previous = state.value;
+            state.value = value;
+        }
+    }
+
+    fn state(&self, action : &str) -> ActionState {
+        self.states.get(action).cloned().unwrap_or_default()
+    }
+
+    //0 for actions that don't exist
+    pub fn value(&self, action : &str) -> f32 {
+        self.state(action).value
+    }
+
+    pub fn pressed(&self, action : &str) -> bool {
+        self.state(action).value != 0.0
+    }
+
+    pub fn just_pressed(&self, action : &str) -> bool {
+        let state = self.state(action);
+        state.value != 0.0 && state.previous == 0.0
+    }
+
+    pub fn just_released(&self, action : &str) -> bool {
+        let state = self.state(action);
+        state.value == 0.0 && state.previous != 0.0
+    }
+
+    //Two actions as one 2D axis, diagonals are scaled back so WASD isn't faster sideways
+    pub fn axis_pair(&self, x : &str, y : &str) -> [f32; 2] {
+        let (x, y) = (self.value(x), self.value(y));
+        let length = (x * x + y * y).sqrt();
+        if length > 1.0 { [x / length, y / length] } else { [x, y] }
+    }
+}
+
+//A button that went down this frame, for binding whatever the player presses next.
+//Keys come before mouse buttons, otherwise which one of several pressed in the same frame is arbitrary.
+pub fn just_pressed_button(input : &Input) -> Option<Button> {
+    input.keys.iter_just_pressed().next().map(|&key| Button::Key(key))
+        .or_else(|| input.mouse_buttons.iter_just_pressed().next().map(|&button| Button::Mouse(button)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::ElementState::{Pressed, Released};
+    use crate::input::tests::{key, mouse_button, mouse_motion, update};
+
+    //Feeds one frame of events to the Input, then updates the actions like InputSystem does
+    fn frame(actions : &mut ActionMap, input : &mut Input, events : &[winit::Event]) {
+        update(input, events);
+        actions.update(input);
+    }
+
+    #[test]
+    fn chord_needs_every_button() {
+        let (mut actions, mut input) = (ActionMap::new(), Input::new());
+        actions.bind("save", Binding::Chord(vec![Button::Key(VirtualKeyCode::LControl), Button::Key(VirtualKeyCode::S)]));
+
+        frame(&mut actions, &mut input, &[key(VirtualKeyCode::S, Pressed)]);
+        assert!(!actions.pressed("save"));
+
+        frame(&mut actions, &mut input, &[key(VirtualKeyCode::LControl, Pressed)]);
+        assert!(actions.pressed("save"));
+        assert!(actions.just_pressed("save"));
+
+        frame(&mut actions, &mut input, &[]);
+        assert!(actions.pressed("save"));
+        assert!(!actions.just_pressed("save"));
+
+        frame(&mut actions, &mut input, &[key(VirtualKeyCode::S, Released)]);
+        assert!(!actions.pressed("save"));
+        assert!(actions.just_released("save"));
+
+        //an empty chord is never held
+        actions.rebind("save", vec![Binding::Chord(Vec::new())]);
+        frame(&mut actions, &mut input, &[]);
+        assert!(!actions.pressed("save"));
+    }
+
+    #[test]
+    fn buttons_axis() {
+        let (mut actions, mut input) = (ActionMap::new(), Input::new());
+        actions.bind("move_x", Binding::Buttons { negative : Button::Key(VirtualKeyCode::A), positive : Button::Key(VirtualKeyCode::D) });
+
+        frame(&mut actions, &mut input, &[key(VirtualKeyCode::A, Pressed)]);
+        assert_eq!(actions.value("move_x"), -1.0);
+
+        frame(&mut actions, &mut input, &[key(VirtualKeyCode::D, Pressed)]);
+        assert_eq!(actions.value("move_x"), 0.0);
+
+        frame(&mut actions, &mut input, &[key(VirtualKeyCode::A, Released)]);
+        assert_eq!(actions.value("move_x"), 1.0);
+        assert_eq!(actions.value("move_y"), 0.0);
+    }
+
+    #[test]
+    fn dead_zone_and_largest_binding() {
+        let (mut actions, mut input) = (ActionMap::new(), Input::new());
+        actions.bind("look_x", Binding::Axis { axis : Axis::MouseMotionX, scale : 0.1 });
+        actions.bind("look_x", Binding::Buttons { negative : Button::Key(VirtualKeyCode::Left), positive : Button::Key(VirtualKeyCode::Right) });
+        actions.set_dead_zone("look_x", 0.5);
+
+        frame(&mut actions, &mut input, &[mouse_motion(4.0, 0.0)]);
+        assert_eq!(actions.value("look_x"), 0.0);
+        assert!(!actions.pressed("look_x"));
+
+        frame(&mut actions, &mut input, &[mouse_motion(-6.0, 0.0)]);
+        assert!((actions.value("look_x") + 0.6).abs() < 1e-6);
+
+        //the binding furthest from 0 wins, whatever its sign
+        frame(&mut actions, &mut input, &[mouse_motion(-6.0, 0.0), key(VirtualKeyCode::Right, Pressed)]);
+        assert_eq!(actions.value("look_x"), 1.0);
+        frame(&mut actions, &mut input, &[mouse_motion(-20.0, 0.0)]);
+        assert_eq!(actions.value("look_x"), -2.0);
+    }
+
+    #[test]
+    fn axis_pair_normalises_diagonals() {
+        let (mut actions, mut input) = (ActionMap::new(), Input::new());
+        actions.bind("move_x", Binding::Buttons { negative : Button::Key(VirtualKeyCode::A), positive : Button::Key(VirtualKeyCode::D) });
+        actions.bind("move_y", Binding::Buttons { negative : Button::Key(VirtualKeyCode::S), positive : Button::Key(VirtualKeyCode::W) });
+        actions.bind("look_x", Binding::Axis { axis : Axis::MouseMotionX, scale : 0.1 });
+        actions.bind("look_y", Binding::Axis { axis : Axis::MouseMotionY, scale : 0.1 });
+
+        frame(&mut actions, &mut input, &[key(VirtualKeyCode::W, Pressed)]);
+        assert_eq!(actions.axis_pair("move_x", "move_y"), [0.0, 1.0]);
+
+        frame(&mut actions, &mut input, &[key(VirtualKeyCode::D, Pressed), mouse_motion(3.0, 4.0)]);
+        let [x, y] = actions.axis_pair("move_x", "move_y");
+        assert!((x - 0.5f32.sqrt()).abs() < 1e-6 && (y - 0.5f32.sqrt()).abs() < 1e-6);
+
+        //shorter than 1 is left alone
+        let [x, y] = actions.axis_pair("look_x", "look_y");
+        assert!((x - 0.3).abs() < 1e-6 && (y - 0.4).abs() < 1e-6);
+    }
+
+    #[test]
+    fn just_pressed_button_prefers_keys() {
+        let mut input = Input::new();
+        update(&mut input, &[mouse_button(MouseButton::Left, Pressed)]);
+        assert_eq!(just_pressed_button(&input), Some(Button::Mouse(MouseButton::Left)));
+
+        update(&mut input, &[mouse_button(MouseButton::Right, Pressed), key(VirtualKeyCode::Space, Pressed)]);
+        assert_eq!(just_pressed_button(&input), Some(Button::Key(VirtualKeyCode::Space)));
+
+        update(&mut input, &[]);
+        assert_eq!(just_pressed_button(&input), None);
+    }
+}
@@ -0,0 +1,341 @@
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::ecs::{Ecs, RefMut};
+use crate::ecs::events::Events;
+use crate::ecs::system::Schedule;
+use crate::renderer::Renderer;
+use crate::window::{Window, WindowConfig};
+
+//Longest frame the simulation catches up on, after a stall the game slows down instead of running ticks forever
+const MAX_FRAME_TIME : Duration = Duration::from_millis(250);
+
+//Timing of the game loop, kept in the Ecs as a resource by App
+#[derive(Debug, Clone)]
+pub struct Time {
+    //real time the last frame took, one tick when headless
+    delta : Duration,
+    //real time since the first frame, simulated time when headless
+    elapsed : Duration,
+    //simulated time one tick advances
+    fixed_delta : Duration,
+    ticks : u64,
+    frame_count : u64,
+    //how far the simulation is into the next tick, from 0 to 1
+    alpha : f32
+}
+
+impl Time {
+    pub fn new(fixed_delta : Duration) -> Time {
+        Time {
+            delta : Duration::from_secs(0),
+            elapsed : Duration::from_secs(0),
+            fixed_delta,
+            ticks : 0,
+            frame_count : 0,
+            alpha : 0.0
+        }
+    }
+
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta.as_secs_f32()
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    //What systems in the fixed stages should step by
+    pub fn fixed_delta(&self) -> Duration {
+        self.fixed_delta
+    }
+
+    pub fn fixed_delta_seconds(&self) -> f32 {
+        self.fixed_delta.as_secs_f32()
+    }
+
+    //Simulated time, lags behind elapsed by less than one tick
+    pub fn fixed_elapsed(&self) -> Duration {
+        //in nanoseconds, multiplying the Duration itself would cut the ticks down to a u32
+        let nanos = self.fixed_delta.as_nanos() * u128::from(self.ticks);
+        Duration::new((nanos / 1_000_000_000) as u64, (nanos % 1_000_000_000) as u32)
+    }
+
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    //Render systems blend between the previous and the current tick's state with this
+    pub fn alpha(&self) -> f32 {
+        self.alpha
+    }
+}
+
+//Owns the window, renderer and Ecs and runs the game loop: the fixed stages of the schedule at a fixed
+//tick rate, then the Render stage and a draw once per frame.
+pub struct App {
+    ecs : Ecs,
+    schedule : Schedule,
+    //dropped before the window its surface was made from
+    renderer : Option<Renderer<'static>>,
+    window : Option<Window>,
+    fixed_delta : Duration,
+    frame_cap : Option<Duration>,
+    clear_colour : [f32; 4]
+}
+
+impl App {
+    pub fn new(config : WindowConfig, schedule : Schedule) -> App {
+        let window = Window::new(config);
+        let renderer = Renderer::new(&window);
+        App {
+            renderer : Some(renderer),
+            window : Some(window),
+            ..App::headless(schedule)
+        }
+    }
+
+    //Without a window or renderer, for tests and servers. Only run_ticks does anything.
+    pub fn headless(schedule : Schedule) -> App {
+        let fixed_delta = Duration::from_secs(1) / 60;
+        let mut ecs = Ecs::new();
+        ecs.add_event::<winit::Event>();
+        ecs.insert_resource(Time::new(fixed_delta));
+        App {
+            ecs,
+            schedule,
+            renderer : None,
+            window : None,
+            fixed_delta,
+            frame_cap : None,
+            clear_colour : [0.0, 0.0, 0.0, 1.0]
+        }
+    }
+
+    //Ticks per second of the fixed stages, 60 by default
+    pub fn with_tick_rate(mut self, ticks_per_second : u32) -> App {
+        self.fixed_delta = Duration::from_secs(1) / ticks_per_second.max(1);
+        self.ecs.insert_resource(Time::new(self.fixed_delta));
+        self
+    }
+
+    //Most frames drawn per second, on top of whatever the present mode waits for
+    pub fn with_frame_cap(mut self, frames_per_second : u32) -> App {
+        self.frame_cap = Some(Duration::from_secs(1) / frames_per_second.max(1));
+        self
+    }
+
+    pub fn with_clear_colour(mut self, colour : [f32; 4]) -> App {
+        self.clear_colour = colour;
+        self
+    }
+
+    pub fn ecs(&self) -> &Ecs {
+        &self.ecs
+    }
+
+    pub fn ecs_mut(&mut self) -> &mut Ecs {
+        &mut self.ecs
+    }
+
+    pub fn window(&self) -> Option<&Window> {
+        self.window.as_ref()
+    }
+
+    fn time(&self) -> RefMut<'_, Time> {
+        self.ecs.resource_mut::<Time>().expect("App keeps a Time resource")
+    }
+
+    fn tick(&mut self) {
+        self.schedule.run_fixed(&mut self.ecs);
+        self.time().ticks += 1;
+    }
+
+    //Runs the fixed stages n times as fast as possible, each tick is a frame of its own that takes fixed_delta
+    pub fn run_ticks(&mut self, n : u64) {
+        for _ in 0..n {
+            {
+                let mut time = self.time();
+                time.delta = self.fixed_delta;
+                time.elapsed += self.fixed_delta;
+            }
+            self.tick();
+            self.schedule.finish_frame(&mut self.ecs);
+            self.time().frame_count += 1;
+        }
+    }
+
+    //Runs until the window is closed, returns right away when headless.
+    //A frame runs as many ticks as time passed and then the Render stage. Events are swapped once at the end
+    //of a frame that ran a tick, so Render systems see all of them even when a frame runs several ticks.
+    pub fn run(&mut self) {
+        let mut last_frame = Instant::now();
+        let mut accumulator = Duration::from_secs(0);
+        loop {
+            let window = match self.window.as_mut() {
+                Some(window) => window,
+                None => return
+            };
+            let running = window.poll_events(&mut self.ecs.resource_mut::<Events<winit::Event>>().expect("App adds window events"));
+            if !running {
+                return;
+            }
+            if window.take_resized() {
+                let (width, height) = window.physical_size();
+                if let Some(renderer) = self.renderer.as_mut() {
+                    renderer.resize(width, height);
+                }
+            }
+
+            let frame_start = Instant::now();
+            let delta = frame_start - last_frame;
+            last_frame = frame_start;
+            {
+                let mut time = self.time();
+                time.delta = delta;
+                time.elapsed += delta;
+            }
+
+            accumulator += delta.min(MAX_FRAME_TIME);
+            while accumulator >= self.fixed_delta {
+                self.tick();
+                accumulator -= self.fixed_delta;
+            }
+
+            self.time().alpha = accumulator.as_secs_f32() / self.fixed_delta.as_secs_f32();
+            self.schedule.run_render(&mut self.ecs);
+            self.schedule.finish_frame(&mut self.ecs);
+            if let Some(renderer) = self.renderer.as_mut() {
+                renderer.draw_clear_colour(self.clear_colour).expect("clear colour failed");
+            }
+            self.time().frame_count += 1;
+
+            if let Some(frame_cap) = self.frame_cap {
+                let spent = frame_start.elapsed();
+                if spent < frame_cap {
+                    thread::sleep(frame_cap - spent);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::events::EventReader;
+    use crate::ecs::Component;
+    use crate::ecs::system::{Access, ExclusiveSystem, FnSystem, Stage};
+
+    //What the systems saw: Time::ticks at every run, and the events they read
+    #[derive(Default)]
+    struct Seen {
+        ticks : Vec<u64>,
+        fixed_events : Vec<u32>,
+        render_events : Vec<u32>
+    }
+
+    fn schedule() -> Schedule {
+        let mut fixed_reader = EventReader::<u32>::new();
+        let mut render_reader = EventReader::<u32>::new();
+        Schedule::builder()
+            .add_system_to_stage(Stage::Update, FnSystem::new("fixed", Access::new().read_resource::<Time>().read_resource::<Events<u32>>().write_resource::<Seen>(), move |ecs : &Ecs| {
+                let mut seen = ecs.resource_mut::<Seen>().unwrap();
+                seen.ticks.push(ecs.resource::<Time>().unwrap().ticks());
+                seen.fixed_events.extend(fixed_reader.iter(&ecs.resource::<Events<u32>>().unwrap()));
+            }))
+            .add_system_to_stage(Stage::Render, FnSystem::new("render", Access::new().read_resource::<Events<u32>>().write_resource::<Seen>(), move |ecs : &Ecs| {
+                let mut seen = ecs.resource_mut::<Seen>().unwrap();
+                seen.render_events.extend(render_reader.iter(&ecs.resource::<Events<u32>>().unwrap()));
+            }))
+            .build()
+            .unwrap()
+    }
+
+    fn headless() -> App {
+        let mut app = App::headless(schedule()).with_tick_rate(50);
+        app.ecs_mut().insert_resource(Seen::default());
+        app.ecs_mut().add_event::<u32>();
+        app
+    }
+
+    #[test]
+    fn headless_ticks() {
+        let mut app = headless();
+        app.run_ticks(3);
+        {
+            let time = app.ecs().resource::<Time>().unwrap();
+            assert_eq!(time.ticks(), 3);
+            assert_eq!(time.frame_count(), 3);
+            assert_eq!(time.delta(), Duration::from_millis(20));
+            assert_eq!(time.fixed_elapsed(), Duration::from_millis(60));
+            assert_eq!(time.elapsed(), time.fixed_elapsed());
+        }
+        assert_eq!(app.ecs().resource::<Seen>().unwrap().ticks, vec![0, 1, 2]);
+
+        app.ecs().send_event(7u32);
+        app.run_ticks(2);
+        let seen = app.ecs().resource::<Seen>().unwrap();
+        assert_eq!(seen.ticks, vec![0, 1, 2, 3, 4]);
+        assert_eq!(seen.fixed_events, vec![7]);
+        assert!(seen.render_events.is_empty());
+    }
+
+    struct Spark;
+    impl Component for Spark {}
+
+    #[test]
+    fn headless_removals_stay_bounded() {
+        //the Render system never runs headless, it mustn't keep removals around for itself
+        let schedule = Schedule::builder()
+            .add_exclusive_system_to_stage(Stage::Update, ExclusiveSystem::new("churn", |ecs : &mut Ecs| {
+                let spark = ecs.spawn((Spark,));
+                ecs.delete_entity(spark);
+            }))
+            .add_system_to_stage(Stage::Render, FnSystem::new("render", Access::new().read::<Spark>(), |_ : &Ecs| ()))
+            .build()
+            .unwrap();
+        let mut app = App::headless(schedule);
+        app.run_ticks(1000);
+        assert!(app.ecs().removed::<Spark>().count() <= 1);
+    }
+
+    #[test]
+    fn fixed_elapsed_past_u32_ticks() {
+        let mut time = Time::new(Duration::from_millis(10));
+        time.ticks = u64::from(u32::MAX) + 11;
+        assert_eq!(time.fixed_elapsed(), Duration::from_millis(u64::from(u32::MAX) * 10 + 110));
+    }
+
+    //What App::run does in a frame, without the window
+    fn frame(app : &mut App, ticks : usize) {
+        for _ in 0..ticks {
+            app.tick();
+        }
+        app.schedule.run_render(&mut app.ecs);
+        app.schedule.finish_frame(&mut app.ecs);
+    }
+
+    #[test]
+    fn every_stage_sees_events_whatever_the_ticks_per_frame() {
+        let mut app = headless();
+        let mut sent = Vec::new();
+        for (i, ticks) in [3, 0, 0, 2, 1, 0, 4].iter().enumerate() {
+            app.ecs().send_event(i as u32);
+            sent.push(i as u32);
+            frame(&mut app, *ticks);
+        }
+        frame(&mut app, 1);
+
+        let seen = app.ecs().resource::<Seen>().unwrap();
+        assert_eq!(seen.fixed_events, sent);
+        assert_eq!(seen.render_events, sent);
+    }
+}
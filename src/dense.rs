@@ -0,0 +1,148 @@
+use crate::handle::Handle;
+use std::iter::Zip;
+use std::slice;
+
+//Values packed next to the raw handle of each, so iteration only touches live values.
+//Shared by GIVec and HandleVec, which map their handles to positions in it.
+pub struct Dense<T, R> {
+    values: Vec<T>,
+    owners: Vec<R>,
+}
+
+impl<T, R: Copy> Dense<T, R> {
+    pub fn with_capacity(capacity: usize) -> Dense<T, R> {
+        Dense {
+            values: Vec::with_capacity(capacity),
+            owners: Vec::with_capacity(capacity),
+        }
+    }
+
+    //Returns the position of the value
+    pub fn push(&mut self, owner: R, value: T) -> usize {
+        self.values.push(value);
+        self.owners.push(owner);
+        self.values.len() - 1
+    }
+
+    pub fn get(&self, position: usize) -> &T {
+        &self.values[position]
+    }
+
+    pub fn get_mut(&mut self, position: usize) -> &mut T {
+        &mut self.values[position]
+    }
+
+    //Safety: no other reference to the value at the position may be alive
+    pub unsafe fn get_unchecked_mut<'a>(dense: *mut Dense<T, R>, position: usize) -> &'a mut T {
+        &mut *(*dense).values.as_mut_ptr().add(position)
+    }
+
+    pub fn owner(&self, position: usize) -> Option<R> {
+        self.owners.get(position).cloned()
+    }
+
+    //The last value takes the place of the removed one, its owner is then found at the same position
+    pub fn swap_remove(&mut self, position: usize) -> (R, T) {
+        (self.owners.swap_remove(position), self.values.swap_remove(position))
+    }
+
+    //Drops the values without handing them back to the container, for when it resets its slots itself
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.owners.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_, T, R> {
+        Iter {
+            inner: self.owners.iter().zip(self.values.iter()),
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, T, R> {
+        IterMut {
+            inner: self.owners.iter().zip(self.values.iter_mut()),
+        }
+    }
+}
+
+//A container keeping its values in a Dense, so drain and retain can go through its remove_at
+pub trait DenseContainer {
+    type Value;
+    type Raw: Copy;
+
+    fn dense_mut(&mut self) -> &mut Dense<Self::Value, Self::Raw>;
+    //Frees the slot of the value at the position, like removing it by its handle
+    fn remove_at(&mut self, position: usize) -> (Handle<Self::Value, Self::Raw>, Self::Value);
+}
+
+pub fn retain<C: DenseContainer, F: FnMut(Handle<C::Value, C::Raw>, &mut C::Value) -> bool>(container: &mut C, mut keep: F) {
+    let mut position = 0;
+    while position < container.dense_mut().len() {
+        let dense = container.dense_mut();
+        let owner = dense.owners[position];
+        if keep(Handle::new(owner), &mut dense.values[position]) {
+            position += 1;
+        } else {
+            container.remove_at(position);
+        }
+    }
+}
+
+pub struct Iter<'a, T, R> {
+    inner: Zip<slice::Iter<'a, R>, slice::Iter<'a, T>>,
+}
+
+impl<'a, T, R: Copy> Iterator for Iter<'a, T, R> {
+    type Item = (Handle<T, R>, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(&owner, value)| (Handle::new(owner), value))
+    }
+}
+
+pub struct IterMut<'a, T, R> {
+    inner: Zip<slice::Iter<'a, R>, slice::IterMut<'a, T>>,
+}
+
+impl<'a, T, R: Copy> Iterator for IterMut<'a, T, R> {
+    type Item = (Handle<T, R>, &'a mut T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(&owner, value)| (Handle::new(owner), value))
+    }
+}
+
+//Takes the values out from the back, freeing their slots as it goes. Values that aren't taken are
+//removed when it is dropped, so the container ends up empty either way.
+pub struct Drain<'a, C: DenseContainer> {
+    container: &'a mut C,
+}
+
+impl<'a, C: DenseContainer> Drain<'a, C> {
+    pub fn new(container: &'a mut C) -> Drain<'a, C> {
+        Drain { container }
+    }
+}
+
+impl<'a, C: DenseContainer> Iterator for Drain<'a, C> {
+    type Item = (Handle<C::Value, C::Raw>, C::Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let last = self.container.dense_mut().len().checked_sub(1)?;
+        Some(self.container.remove_at(last))
+    }
+}
+
+impl<'a, C: DenseContainer> Drop for Drain<'a, C> {
+    fn drop(&mut self) {
+        self.for_each(drop);
+    }
+}
@@ -0,0 +1,210 @@
+use super::storage::{Storage, StorageType};
+use super::{Component, ComponentRegister, Ecs, Handle};
+use std::any::TypeId;
+use std::collections::HashMap;
+use typemap::ShareMap;
+
+//A table of all entities that have exactly the same set of component types.
+//The components themselves live in the Columns of their type, at the same row.
+pub struct Archetype {
+    types: Vec<TypeId>,
+    pub(super) entities: Vec<Handle>,
+    //cached archetypes reached by adding or removing one component type
+    add_edges: HashMap<TypeId, usize>,
+    remove_edges: HashMap<TypeId, usize>,
+}
+
+impl Archetype {
+    pub(super) fn new(types: Vec<TypeId>) -> Archetype {
+        Archetype {
+            types,
+            entities: Vec::new(),
+            add_edges: HashMap::new(),
+            remove_edges: HashMap::new(),
+        }
+    }
+
+    pub fn has(&self, ty: TypeId) -> bool {
+        self.types.binary_search(&ty).is_ok()
+    }
+
+    pub(super) fn types(&self) -> &[TypeId] {
+        &self.types
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.entities.len()
+    }
+}
+
+//All archetypes of an Ecs, the first one is the empty archetype new entities start in
+pub(super) struct Archetypes {
+    archetypes: Vec<Archetype>,
+    index: HashMap<Vec<TypeId>, usize>,
+}
+
+impl Archetypes {
+    pub(super) fn new() -> Archetypes {
+        let mut index = HashMap::new();
+        index.insert(Vec::new(), 0);
+        Archetypes {
+            archetypes: vec![Archetype::new(Vec::new())],
+            index,
+        }
+    }
+
+    pub(super) const EMPTY: usize = 0;
+
+    pub(super) fn get(&self, archetype: usize) -> &Archetype {
+        &self.archetypes[archetype]
+    }
+
+    pub(super) fn get_mut(&mut self, archetype: usize) -> &mut Archetype {
+        &mut self.archetypes[archetype]
+    }
+
+    pub(super) fn iter(&self) -> std::slice::Iter<'_, Archetype> {
+        self.archetypes.iter()
+    }
+
+    //The types have to be sorted
+    pub(super) fn find_or_create(&mut self, types: Vec<TypeId>) -> usize {
+        if let Some(&archetype) = self.index.get(&types) {
+            return archetype;
+        }
+
+        let archetype = self.archetypes.len();
+        self.index.insert(types.clone(), archetype);
+        self.archetypes.push(Archetype::new(types));
+        archetype
+    }
+
+    //Empties every table, the archetypes and the edges between them stay
+    pub(super) fn clear(&mut self) {
+        for archetype in &mut self.archetypes {
+            archetype.entities.clear();
+        }
+    }
+
+    pub(super) fn with(&mut self, from: usize, ty: TypeId) -> usize {
+        if let Some(&to) = self.archetypes[from].add_edges.get(&ty) {
+            return to;
+        }
+
+        let mut types = self.archetypes[from].types.clone();
+        types.push(ty);
+        types.sort();
+        let to = self.find_or_create(types);
+        self.archetypes[from].add_edges.insert(ty, to);
+        self.archetypes[to].remove_edges.insert(ty, from);
+        to
+    }
+
+    pub(super) fn without(&mut self, from: usize, ty: TypeId) -> usize {
+        if let Some(&to) = self.archetypes[from].remove_edges.get(&ty) {
+            return to;
+        }
+
+        let types = self.archetypes[from].types.iter().cloned().filter(|t| *t != ty).collect();
+        let to = self.find_or_create(types);
+        self.archetypes[from].remove_edges.insert(ty, to);
+        self.archetypes[to].add_edges.insert(ty, from);
+        to
+    }
+}
+
+//Every archetype's column of one component type, indexed by archetype
+pub struct Columns<T> {
+    columns: Vec<Vec<T>>,
+}
+
+impl<T> Columns<T> {
+    pub(super) fn new() -> Columns<T> {
+        Columns {
+            columns: Vec::new(),
+        }
+    }
+
+    pub(super) fn get(&self, archetype: usize, row: usize) -> Option<&T> {
+        self.columns.get(archetype).and_then(|column| column.get(row))
+    }
+
+    pub(super) fn get_mut(&mut self, archetype: usize, row: usize) -> Option<&mut T> {
+        self.columns.get_mut(archetype).and_then(|column| column.get_mut(row))
+    }
+
+    pub(super) fn column_mut(&mut self, archetype: usize) -> &mut Vec<T> {
+        if self.columns.len() <= archetype {
+            self.columns.resize_with(archetype + 1, Vec::new);
+        }
+        &mut self.columns[archetype]
+    }
+
+    //Safety: the row must exist and no other reference to it may be alive
+    pub(super) unsafe fn get_unchecked_mut<'a>(columns: *mut Columns<T>, archetype: usize, row: usize) -> &'a mut T {
+        let column = (*columns).columns.as_mut_ptr().add(archetype);
+        &mut *(*column).as_mut_ptr().add(row)
+    }
+}
+
+fn columns_mut<T: Component + 'static>(components: &mut ShareMap) -> &mut Columns<T> {
+    components.get_mut::<ComponentRegister<T>>().expect("component type isn't registered").get_mut().columns_mut()
+}
+
+fn move_row<T: Component + 'static>(components: &mut ShareMap, from: usize, row: usize, to: usize) {
+    let columns = columns_mut::<T>(components);
+    let comp = columns.column_mut(from).swap_remove(row);
+    columns.column_mut(to).push(comp);
+}
+
+fn swap_rows<T: Component + 'static>(components: &mut ShareMap, archetype: usize, a: usize, b: usize) {
+    columns_mut::<T>(components).column_mut(archetype).swap(a, b);
+}
+
+//Pops the entity's T off the end of the column, where delete_entity put its row, and runs the hook on it.
+//The store isn't borrowed while the hook runs.
+fn take_last<T: Component + 'static>(ecs: &mut Ecs, archetype: usize, entity: &Handle) {
+    let mut comp = columns_mut::<T>(&mut ecs.components).column_mut(archetype).pop().expect("entity's row was moved to the end");
+    comp.on_remove(ecs, entity);
+}
+
+//Takes the entity's T out of its sparse set or GIVec and runs the hook on it, returns whether the entity had one
+fn take_sparse<T: Component + 'static>(ecs: &mut Ecs, entity: &Handle) -> bool {
+    let storage = ecs.components.get_mut::<ComponentRegister<T>>().expect("component type isn't registered").get_mut();
+    match storage.remove_by_entity(entity) {
+        Some(mut comp) => {
+            comp.on_remove(ecs, entity);
+            true
+        }
+        None => false,
+    }
+}
+
+//Drops every T without running hooks
+fn clear<T: Component + 'static>(components: &mut ShareMap) {
+    *components.get_mut::<ComponentRegister<T>>().expect("component type isn't registered").get_mut() = Storage::new(T::STORAGE);
+}
+
+//Operations on the storage of a component type for when only its TypeId is known
+#[derive(Clone, Copy)]
+pub(super) struct ComponentInfo {
+    pub(super) storage: StorageType,
+    pub(super) move_row: fn(&mut ShareMap, usize, usize, usize),
+    pub(super) swap_rows: fn(&mut ShareMap, usize, usize, usize),
+    pub(super) take_last: fn(&mut Ecs, usize, &Handle),
+    pub(super) take_sparse: fn(&mut Ecs, &Handle) -> bool,
+    pub(super) clear: fn(&mut ShareMap),
+}
+
+impl ComponentInfo {
+    pub(super) fn of<T: Component + 'static>() -> ComponentInfo {
+        ComponentInfo {
+            storage: T::STORAGE,
+            move_row: move_row::<T>,
+            swap_rows: swap_rows::<T>,
+            take_last: take_last::<T>,
+            take_sparse: take_sparse::<T>,
+            clear: clear::<T>,
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use super::{Component, Ecs, Handle};
+use std::any::TypeId;
+
+//A group of components that are inserted together, the entity only moves archetype once
+pub trait Bundle: Send + 'static {
+    //Registers the component types of the bundle and returns them in order
+    fn register(ecs: &mut Ecs) -> Vec<TypeId>;
+    //Runs Component::on_add of every component before the entity is changed
+    fn on_add(&mut self, ecs: &Ecs, handle: &Handle);
+    //Stores every component, from is the archetype the entity was in before the bundle was inserted
+    fn put(self, ecs: &mut Ecs, handle: &Handle, from: usize);
+}
+
+macro_rules! impl_bundle_tuple {
+    ($($name:ident),*) => {
+        #[allow(non_snake_case)]
+        impl<$($name: Component + 'static),*> Bundle for ($($name,)*) {
+            fn register(ecs: &mut Ecs) -> Vec<TypeId> {
+                $(ecs.register_type::<$name>();)*
+                vec![$(TypeId::of::<$name>()),*]
+            }
+
+            fn on_add(&mut self, ecs: &Ecs, handle: &Handle) {
+                let ($($name,)*) = self;
+                $($name.on_add(ecs, handle);)*
+            }
+
+            //the replaced components are only handed to their hooks once every component is in place
+            fn put(self, ecs: &mut Ecs, handle: &Handle, from: usize) {
+                let ($($name,)*) = self;
+                let ($($name,)*) = ($(ecs.put_comp(handle, from, $name),)*);
+                $(if let Some(mut old) = $name {
+                    old.on_replace(ecs, handle);
+                })*
+            }
+        }
+    };
+}
+
+impl_bundle_tuple!(A);
+impl_bundle_tuple!(A, B);
+impl_bundle_tuple!(A, B, C);
+impl_bundle_tuple!(A, B, C, D);
+impl_bundle_tuple!(A, B, C, D, E);
+impl_bundle_tuple!(A, B, C, D, E, F);
+impl_bundle_tuple!(A, B, C, D, E, F, G);
+impl_bundle_tuple!(A, B, C, D, E, F, G, H);
+
+//Changes one entity, returned by Ecs::entity
+pub struct EntityMut<'w> {
+    ecs: &'w mut Ecs,
+    handle: Handle,
+}
+
+impl<'w> EntityMut<'w> {
+    pub(super) fn new(ecs: &'w mut Ecs, handle: Handle) -> EntityMut<'w> {
+        EntityMut { ecs, handle }
+    }
+
+    pub fn id(&self) -> Handle {
+        self.handle
+    }
+
+    pub fn insert<T: Component + 'static>(&mut self, comp: T) -> &mut Self {
+        self.ecs.add_comp(&self.handle, comp).expect("entity was checked when it was borrowed");
+        self
+    }
+
+    pub fn insert_bundle<B: Bundle>(&mut self, bundle: B) -> &mut Self {
+        self.ecs.insert_bundle(&self.handle, bundle).expect("entity was checked when it was borrowed");
+        self
+    }
+
+    pub fn remove<T: Component + 'static>(&mut self) -> Option<T> {
+        self.ecs.remove_comp::<T>(&self.handle)
+    }
+
+    pub fn despawn(self) {
+        self.ecs.delete_entity(self.handle);
+    }
+}
@@ -0,0 +1,172 @@
+use std::cell::UnsafeCell;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+//RefCell that can be shared between threads. Conflicting borrows fail instead of blocking,
+//systems declare their access up front so a conflict is a bug rather than contention.
+pub struct AtomicRefCell<T> {
+    borrows: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+const WRITING: usize = usize::MAX;
+
+unsafe impl<T: Send> Send for AtomicRefCell<T> {}
+unsafe impl<T: Send + Sync> Sync for AtomicRefCell<T> {}
+
+#[derive(Debug)]
+pub struct BorrowError;
+
+impl<T> AtomicRefCell<T> {
+    pub fn new(value: T) -> AtomicRefCell<T> {
+        AtomicRefCell {
+            borrows: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn try_borrow(&self) -> Result<Ref<'_, T>, BorrowError> {
+        let mut current = self.borrows.load(Ordering::Relaxed);
+        loop {
+            if current == WRITING || current == WRITING - 1 {
+                return Err(BorrowError);
+            }
+            match self.borrows.compare_exchange_weak(current, current + 1, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(actual) => current = actual,
+            }
+        }
+
+        Ok(Ref {
+            value: unsafe { &*self.value.get() },
+            borrows: &self.borrows,
+        })
+    }
+
+    pub fn try_borrow_mut(&self) -> Result<RefMut<'_, T>, BorrowError> {
+        self.borrows
+            .compare_exchange(0, WRITING, Ordering::Acquire, Ordering::Relaxed)
+            .map_err(|_| BorrowError)?;
+
+        Ok(RefMut {
+            value: unsafe { &mut *self.value.get() },
+            borrows: &self.borrows,
+        })
+    }
+
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.try_borrow().expect("already mutably borrowed")
+    }
+
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        self.try_borrow_mut().expect("already borrowed")
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.value.get() }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+pub struct Ref<'a, T: ?Sized> {
+    value: &'a T,
+    borrows: &'a AtomicUsize,
+}
+
+impl<'a, T: ?Sized> Ref<'a, T> {
+    pub fn map<U: ?Sized, F: FnOnce(&T) -> &U>(orig: Ref<'a, T>, f: F) -> Ref<'a, U> {
+        let borrows = orig.borrows;
+        let value = f(orig.value);
+        std::mem::forget(orig);
+        Ref { value, borrows }
+    }
+
+    pub fn filter_map<U: ?Sized, F: FnOnce(&T) -> Option<&U>>(orig: Ref<'a, T>, f: F) -> Result<Ref<'a, U>, Ref<'a, T>> {
+        match f(orig.value) {
+            Some(value) => {
+                let borrows = orig.borrows;
+                std::mem::forget(orig);
+                Ok(Ref { value, borrows })
+            }
+            None => Err(orig),
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for Ref<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<'a, T: ?Sized> Drop for Ref<'a, T> {
+    fn drop(&mut self) {
+        self.borrows.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl<'a, T: ?Sized + std::fmt::Debug> std::fmt::Debug for Ref<'a, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+pub struct RefMut<'a, T: ?Sized> {
+    value: &'a mut T,
+    borrows: &'a AtomicUsize,
+}
+
+impl<'a, T: ?Sized> RefMut<'a, T> {
+    pub fn map<U: ?Sized, F: FnOnce(&mut T) -> &mut U>(orig: RefMut<'a, T>, f: F) -> RefMut<'a, U> {
+        let borrows = orig.borrows;
+        let value = unsafe { &mut *(orig.value as *mut T) };
+        std::mem::forget(orig);
+        RefMut {
+            value: f(value),
+            borrows,
+        }
+    }
+
+    pub fn filter_map<U: ?Sized, F: FnOnce(&mut T) -> Option<&mut U>>(orig: RefMut<'a, T>, f: F) -> Result<RefMut<'a, U>, RefMut<'a, T>> {
+        let value = unsafe { &mut *(orig.value as *mut T) };
+        match f(value) {
+            Some(value) => {
+                let borrows = orig.borrows;
+                std::mem::forget(orig);
+                Ok(RefMut { value, borrows })
+            }
+            None => Err(orig),
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RefMut<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for RefMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.value
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RefMut<'a, T> {
+    fn drop(&mut self) {
+        self.borrows.store(0, Ordering::Release);
+    }
+}
+
+impl<'a, T: ?Sized + std::fmt::Debug> std::fmt::Debug for RefMut<'a, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.value.fmt(f)
+    }
+}
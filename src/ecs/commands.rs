@@ -0,0 +1,78 @@
+use super::{Bundle, Component, Ecs, Handle};
+
+pub(super) type Command = Box<dyn FnOnce(&mut Ecs) + Send>;
+
+//Records structural changes while the Ecs is only shared, e.g. inside a system or while iterating a query.
+//The recorded commands are queued on the Ecs when this is dropped and run, in order, by Ecs::apply_commands,
+//which the schedule calls after every stage.
+pub struct Commands<'w> {
+    ecs: &'w Ecs,
+    queue: Vec<Command>,
+}
+
+impl<'w> Commands<'w> {
+    pub(super) fn new(ecs: &'w Ecs) -> Commands<'w> {
+        Commands {
+            ecs,
+            queue: Vec::new(),
+        }
+    }
+
+    fn push<F: FnOnce(&mut Ecs) + Send + 'static>(&mut self, command: F) {
+        self.queue.push(Box::new(command));
+    }
+
+    //The handle is reserved right away, the entity exists once the commands are applied
+    pub fn spawn<B: Bundle>(&mut self, bundle: B) -> Handle {
+        let handle = self.ecs.entities.reserve();
+        self.push(move |ecs| {
+            ecs.spawn_reserved(&handle);
+            ecs.insert_bundle(&handle, bundle).ok();
+        });
+        handle
+    }
+
+    pub fn despawn(&mut self, handle: &Handle) {
+        let handle = *handle;
+        self.push(move |ecs| {
+            ecs.delete_entity(handle);
+        });
+    }
+
+    pub fn despawn_recursive(&mut self, handle: &Handle) {
+        let handle = *handle;
+        self.push(move |ecs| {
+            ecs.despawn_recursive(handle);
+        });
+    }
+
+    //Entities that are gone by the time the commands are applied are skipped
+    pub fn insert<T: Component + 'static>(&mut self, handle: &Handle, comp: T) {
+        let handle = *handle;
+        self.push(move |ecs| {
+            ecs.add_comp(&handle, comp).ok();
+        });
+    }
+
+    pub fn insert_bundle<B: Bundle>(&mut self, handle: &Handle, bundle: B) {
+        let handle = *handle;
+        self.push(move |ecs| {
+            ecs.insert_bundle(&handle, bundle).ok();
+        });
+    }
+
+    pub fn remove<T: Component + 'static>(&mut self, handle: &Handle) {
+        let handle = *handle;
+        self.push(move |ecs| {
+            ecs.remove_comp::<T>(&handle);
+        });
+    }
+}
+
+impl<'w> Drop for Commands<'w> {
+    fn drop(&mut self) {
+        if !self.queue.is_empty() {
+            self.ecs.commands.lock().unwrap().append(&mut self.queue);
+        }
+    }
+}
@@ -1,8 +1,6 @@
-use typemap::TypeMap;
-
-pub struct Entity(pub TypeMap);
-impl Entity {
-    pub fn new() -> Entity {
-        Entity(TypeMap::new())
-    }
-}
\ No newline at end of file
+//Where an entity's components are stored: its archetype and its row in that archetype's table
+#[derive(Debug, Clone, Copy)]
+pub struct Entity {
+    pub archetype: usize,
+    pub row: usize,
+}
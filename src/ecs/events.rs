@@ -0,0 +1,94 @@
+use super::{Ecs, Resource};
+use std::marker::PhantomData;
+
+//A channel of events of one type, stored as a resource by Ecs::add_event.
+//Events are kept for the frame they are sent in and the one after, so a reader that runs once per frame
+//sees every event no matter if it runs before or after the sender.
+pub struct Events<E> {
+    previous: Vec<E>,
+    current: Vec<E>,
+    //number of events sent before the first one of each buffer
+    previous_start: usize,
+    current_start: usize,
+}
+
+impl<E> Events<E> {
+    pub fn new() -> Events<E> {
+        Events {
+            previous: Vec::new(),
+            current: Vec::new(),
+            previous_start: 0,
+            current_start: 0,
+        }
+    }
+
+    pub fn send(&mut self, event: E) {
+        self.current.push(event);
+    }
+
+    //Number of events sent since the channel was created
+    fn sent(&self) -> usize {
+        self.current_start + self.current.len()
+    }
+
+    //Drops the events of the previous frame, the ones of this frame become the previous ones.
+    //Schedules do this once per frame for every channel registered with add_event.
+    pub fn update(&mut self) {
+        std::mem::swap(&mut self.previous, &mut self.current);
+        self.current.clear();
+        self.previous_start = self.current_start;
+        self.current_start += self.previous.len();
+    }
+
+    //A reader that only sees events sent from now on
+    pub fn reader(&self) -> EventReader<E> {
+        EventReader {
+            next: self.sent(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<E> Default for Events<E> {
+    fn default() -> Events<E> {
+        Events::new()
+    }
+}
+
+//Cursor into an Events<E>, every system keeps its own so they don't take events from each other
+pub struct EventReader<E> {
+    //count of the first event this reader hasn't seen
+    next: usize,
+    marker: PhantomData<fn() -> E>,
+}
+
+impl<E> EventReader<E> {
+    //Starts at the oldest event the channel still holds
+    pub fn new() -> EventReader<E> {
+        EventReader {
+            next: 0,
+            marker: PhantomData,
+        }
+    }
+
+    //Events sent since the last call, oldest first. Events that were dropped before this reader got to them are missed.
+    pub fn iter<'e>(&mut self, events: &'e Events<E>) -> impl Iterator<Item = &'e E> {
+        let unread = |buffer: &'e [E], start: usize| &buffer[self.next.saturating_sub(start).min(buffer.len())..];
+        let previous = unread(&events.previous, events.previous_start);
+        let current = unread(&events.current, events.current_start);
+        self.next = events.sent();
+        previous.iter().chain(current.iter())
+    }
+}
+
+impl<E> Default for EventReader<E> {
+    fn default() -> EventReader<E> {
+        EventReader::new()
+    }
+}
+
+pub(super) fn update_events<E: Resource>(ecs: &Ecs) {
+    if let Some(mut events) = ecs.resource_mut::<Events<E>>() {
+        events.update();
+    }
+}
@@ -1,101 +1,362 @@
-#[derive(Debug, Clone)]
-pub struct Index {
-    pub index: usize,
-    generation: usize,
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+//How an Index is packed, the bits above the slot index hold the generation
+pub trait IndexWidth: 'static {
+    type Bits: Copy + Ord + Hash + fmt::Debug + Send + Sync + Serialize + DeserializeOwned;
+    const INDEX_BITS: u32;
+    const GENERATION_BITS: u32;
+
+    fn pack(index: usize, generation: u64) -> Self::Bits;
+    fn unpack(bits: Self::Bits) -> (usize, u64);
+
+    fn max_index() -> usize {
+        ((1u64 << Self::INDEX_BITS) - 1) as usize
+    }
+
+    fn max_generation() -> u64 {
+        (1u64 << Self::GENERATION_BITS) - 1
+    }
 }
 
-enum Entry {
-    Live(usize),
-    Dead(usize),
+macro_rules! index_width {
+    ($name:ident, $bits:ty, $index_bits:expr) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub struct $name;
+
+        impl IndexWidth for $name {
+            type Bits = $bits;
+            const INDEX_BITS: u32 = $index_bits;
+            const GENERATION_BITS: u32 = <$bits>::BITS - $index_bits;
+
+            fn pack(index: usize, generation: u64) -> $bits {
+                ((generation as $bits) << Self::INDEX_BITS) | index as $bits
+            }
+
+            fn unpack(bits: $bits) -> (usize, u64) {
+                ((bits & (<$bits>::MAX >> Self::GENERATION_BITS)) as usize, (bits >> Self::INDEX_BITS) as u64)
+            }
+        }
+    };
+}
+
+//32 bit slot index and 32 bit generation
+index_width!(Bits64, u64, 32);
+//20 bit slot index and 12 bit generation, for handles that are stored or sent in bulk
+index_width!(Bits32, u32, 20);
+
+pub struct Index<W: IndexWidth = Bits64> {
+    bits: W::Bits,
+}
+
+impl<W: IndexWidth> Index<W> {
+    fn new(index: usize, generation: u64) -> Index<W> {
+        Index {
+            bits: W::pack(index, generation),
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        W::unpack(self.bits).0
+    }
+
+    pub fn generation(&self) -> u64 {
+        W::unpack(self.bits).1
+    }
+}
+
+impl<W: IndexWidth> Clone for Index<W> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<W: IndexWidth> Copy for Index<W> {}
+
+impl<W: IndexWidth> PartialEq for Index<W> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bits == other.bits
+    }
+}
+
+impl<W: IndexWidth> Eq for Index<W> {}
+
+impl<W: IndexWidth> PartialOrd for Index<W> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<W: IndexWidth> Ord for Index<W> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.bits.cmp(&other.bits)
+    }
+}
+
+impl<W: IndexWidth> Hash for Index<W> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.bits.hash(state)
+    }
+}
+
+//Human readable formats like the JSON of scenes get the index and generation apart, binary ones like
+//snapshots get the packed bits. Either reads back as the same Index.
+#[derive(Serialize, Deserialize)]
+struct IndexParts {
+    index: usize,
+    generation: u64,
 }
 
-impl Entry {
-    fn is_live(&self) -> bool {
-        match self {
-            Entry::Live(_) => true,
-            Entry::Dead(_) => false,
+impl<W: IndexWidth> Serialize for Index<W> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            IndexParts {
+                index: self.index(),
+                generation: self.generation(),
+            }
+            .serialize(serializer)
+        } else {
+            self.bits.serialize(serializer)
         }
     }
+}
 
-    fn value(&self) -> usize {
-        match self {
-            Entry::Live(x) | Entry::Dead(x) => x.clone(),
+impl<'de, W: IndexWidth> Deserialize<'de> for Index<W> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Index<W>, D::Error> {
+        if !deserializer.is_human_readable() {
+            return W::Bits::deserialize(deserializer).map(|bits| Index { bits });
         }
+
+        let parts = IndexParts::deserialize(deserializer)?;
+        if parts.index > W::max_index() || parts.generation > W::max_generation() {
+            return Err(serde::de::Error::custom(format!(
+                "index {} generation {} doesn't fit in {} bits",
+                parts.index,
+                parts.generation,
+                W::INDEX_BITS + W::GENERATION_BITS
+            )));
+        }
+        Ok(Index::new(parts.index, parts.generation))
+    }
+}
+
+impl<W: IndexWidth> fmt::Debug for Index<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Index").field("index", &self.index()).field("generation", &self.generation()).finish()
     }
 }
 
-pub struct Allocator {
+#[derive(Serialize, Deserialize)]
+enum Entry {
+    Live(u64),
+    //dead slots form a linked list through the slot of the next free one
+    Dead(u64, Option<usize>),
+    //the generation saturated, the slot is never handed out again so old handles can't alias a new value
+    Retired,
+}
+
+pub struct Allocator<W: IndexWidth = Bits64> {
     generations: Vec<Entry>,
+    //most recently released slot, allocation and release are O(1)
+    free: Option<usize>,
+    //indices handed out by reserve() that don't have an entry yet, they follow the last entry
+    reserved: AtomicUsize,
+    marker: std::marker::PhantomData<W>,
 }
 
-impl Allocator {
-    pub fn new() -> Allocator {
+//Reservations that weren't flushed are left out, a restored allocator hands them out again
+impl<W: IndexWidth> Serialize for Allocator<W> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (&self.generations, self.free).serialize(serializer)
+    }
+}
+
+impl<'de, W: IndexWidth> Deserialize<'de> for Allocator<W> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Allocator<W>, D::Error> {
+        let (generations, free) = <(Vec<Entry>, Option<usize>)>::deserialize(deserializer)?;
+        Ok(Allocator {
+            generations,
+            free,
+            reserved: AtomicUsize::new(0),
+            marker: std::marker::PhantomData,
+        })
+    }
+}
+
+impl<W: IndexWidth> Allocator<W> {
+    pub fn new() -> Allocator<W> {
         Allocator {
             generations: Vec::new(),
+            free: None,
+            reserved: AtomicUsize::new(0),
+            marker: std::marker::PhantomData,
+        }
+    }
+
+    fn fresh(index: usize) -> Index<W> {
+        assert!(index <= W::max_index(), "generational index ran out of slots");
+        Index::new(index, 0)
+    }
+
+    //Hands out a fresh index without needing exclusive access, it becomes live with the next flush
+    pub fn reserve(&self) -> Index<W> {
+        Self::fresh(self.generations.len() + self.reserved.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn flush(&mut self) {
+        let reserved = std::mem::replace(self.reserved.get_mut(), 0);
+        for _ in 0..reserved {
+            self.generations.push(Entry::Live(0));
         }
     }
 
-    pub fn get(&mut self) -> Index {
-        let first = self
-            .generations
-            .iter_mut()
-            .skip_while(|x| x.is_live())
-            .enumerate()
-            .next();
-
-        if let Some((index, entry)) = first {
-            match entry {
-                &mut Entry::Dead(gen) => {
-                    *entry = Entry::Live(gen+1);
-                    Index {
-                        index: index,
-                        generation: gen + 1,
-                    }
+    pub fn get(&mut self) -> Index<W> {
+        self.flush();
+
+        if let Some(index) = self.free {
+            match self.generations[index] {
+                Entry::Dead(gen, next) => {
+                    self.free = next;
+                    self.generations[index] = Entry::Live(gen + 1);
+                    Index::new(index, gen + 1)
                 }
-                _ => panic!("Found Live entry in get function"),
+                _ => panic!("Found Live entry in free list"),
             }
         } else {
+            let index = Self::fresh(self.generations.len());
             self.generations.push(Entry::Live(0));
-            Index {
-                index: self.generations.len() - 1,
-                generation: 0,
+            index
+        }
+    }
+
+    pub fn release(&mut self, index: Index<W>) -> bool {
+        let slot = index.index();
+        match self.generations.get(slot) {
+            Some(&Entry::Live(gen)) if gen == index.generation() => {
+                if gen == W::max_generation() {
+                    self.generations[slot] = Entry::Retired;
+                } else {
+                    self.generations[slot] = Entry::Dead(gen, self.free);
+                    self.free = Some(slot);
+                }
+                true
             }
+            _ => false,
+        }
+    }
+
+    pub fn is_live(&self, index: &Index<W>) -> bool {
+        match self.generations.get(index.index()) {
+            Some(&Entry::Live(generation)) => generation == index.generation(),
+            _ => false,
         }
     }
+}
 
-    pub fn release(&mut self, index: Index) -> bool {
-        let Index { index, generation } = index;
-        self.generations.get_mut(index).map_or_else(
-            || false,
-            |entry| {
-                if let Entry::Live(gen) = entry {
-                    if *gen == generation {
-                        *entry = Entry::Dead(*gen);
-                        true
-                    } else {
-                        false
-                    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //xorshift, so failures reproduce from the seed without pulling in a crate
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    //Runs random gets and releases against a model that holds the live generation of every slot,
+    //returns how many slots were used
+    fn check_against_model<W: IndexWidth>(seed: u64, steps: usize, max_live: usize) -> usize {
+        let mut rng = Rng(seed);
+        let mut allocator = Allocator::<W>::new();
+        let mut model: Vec<Option<u64>> = Vec::new();
+        //highest generation each slot handed out, reused slots have to go above it
+        let mut highest: Vec<u64> = Vec::new();
+        let mut handed_out: Vec<Index<W>> = Vec::new();
+
+        for _ in 0..steps {
+            //more gets than releases early on, then mostly churn on few slots so generations climb
+            let live = model.iter().filter(|slot| slot.is_some()).count();
+            if live == 0 || (live < max_live && rng.below(3) != 0) {
+                let index = allocator.get();
+                let slot = index.index();
+                if slot == model.len() {
+                    assert_eq!(index.generation(), 0, "seed {}", seed);
+                    model.push(Some(0));
+                    highest.push(0);
+                } else {
+                    assert_eq!(model[slot], None, "seed {}: handed out a live slot", seed);
+                    assert!(index.generation() > highest[slot], "seed {}: generation didn't increase", seed);
+                    model[slot] = Some(index.generation());
+                    highest[slot] = index.generation();
+                }
+                handed_out.push(index);
+            } else {
+                //mostly live handles, the rest are likely stale
+                let index = if rng.below(4) != 0 {
+                    let live: Vec<&Index<W>> = handed_out.iter().filter(|index| model[index.index()] == Some(index.generation())).collect();
+                    *live[rng.below(live.len())]
                 } else {
-                    false
+                    handed_out[rng.below(handed_out.len())]
+                };
+                let expected = model[index.index()] == Some(index.generation());
+                assert_eq!(allocator.release(index), expected, "seed {}", seed);
+                if expected {
+                    model[index.index()] = None;
                 }
-            },
-        )
-        // if let Some(Entry::Live(gen)) = self.generations.get(index) {
-        //     if *gen == generation {
-        //         self.generations[index] = Entry::Dead(*gen);
-        //         true
-        //     } else {
-        //         false
-        //     }
-        // }
-        // else {
-        //     false
-        // }
-    }
-
-    pub fn is_live(&self, index: &Index) -> bool {
-        self.generations
-            .get(index.index)
-            .map_or_else(|| false, |x| x.is_live() && x.value() == index.generation)
+            }
+
+            for index in &handed_out {
+                let live = model[index.index()] == Some(index.generation());
+                assert_eq!(allocator.is_live(index), live, "seed {}: stale handle resolved", seed);
+            }
+            handed_out.retain(|index| index.generation() + 16 > highest[index.index()]);
+        }
+        model.len()
+    }
+
+    #[test]
+    fn allocator_matches_model() {
+        for seed in 1..50u64 {
+            check_against_model::<Bits64>(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15), 2000, 16);
+        }
+    }
+
+    //12 bit generations saturate within the run, so slots get retired and new ones are needed
+    #[test]
+    fn narrow_allocator_matches_model() {
+        for seed in 1..3u64 {
+            let slots = check_against_model::<Bits32>(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15), 60_000, 4);
+            assert!(slots > 4, "seed {}: no slot was retired", seed);
+        }
+    }
+
+    #[test]
+    fn serialized_forms() {
+        let index = Index::<Bits64>::new(3, 1);
+        let json = serde_json::to_string(&index).unwrap();
+        assert_eq!(json, r#"{"index":3,"generation":1}"#);
+        assert_eq!(serde_json::from_str::<Index<Bits64>>(&json).unwrap(), index);
+
+        let binary = bincode::serialize(&index).unwrap();
+        assert_eq!(binary, bincode::serialize(&(1u64 << 32 | 3)).unwrap());
+        assert_eq!(bincode::deserialize::<Index<Bits64>>(&binary).unwrap(), index);
+
+        //a generation that doesn't fit would otherwise end up in the slot index
+        assert!(serde_json::from_str::<Index<Bits32>>(r#"{"index":3,"generation":4096}"#).is_err());
+        assert!(serde_json::from_str::<Index<Bits32>>(r#"{"index":1048576,"generation":0}"#).is_err());
     }
+
 }
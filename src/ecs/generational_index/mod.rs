@@ -1,47 +1,167 @@
-mod index;
-pub use index::Index;
+pub mod index;
+pub use index::{Bits64, Index, IndexWidth};
 
-pub struct GIVec<T> {
-    allocator : index::Allocator,
-    vec : Vec<T>
+use crate::dense::{self, Dense, DenseContainer};
+
+//Handle to an element of a GIVec<T, W>
+pub type Handle<T, W = Bits64> = crate::handle::Handle<T, Index<W>>;
+
+impl<T, W : IndexWidth> Handle<T, W> {
+    pub fn index(&self) -> usize {
+        self.raw().index()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.raw().generation()
+    }
 }
 
-impl<T> GIVec<T> {
-    pub fn new() -> GIVec<T> {
+pub type Iter<'a, T, W = Bits64> = dense::Iter<'a, T, Index<W>>;
+pub type IterMut<'a, T, W = Bits64> = dense::IterMut<'a, T, Index<W>>;
+pub type Drain<'a, T, W = Bits64> = dense::Drain<'a, GIVec<T, W>>;
+
+//The index width is picked per GIVec, Bits64 unless handles need to be smaller
+pub struct GIVec<T, W : IndexWidth = Bits64> {
+    allocator : index::Allocator<W>,
+    //position in elements for every slot that holds one
+    slots : Vec<Option<usize>>,
+    elements : Dense<T, Index<W>>
+}
+
+impl<T, W : IndexWidth> GIVec<T, W> {
+    pub fn new() -> GIVec<T, W> {
+        GIVec::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity : usize) -> GIVec<T, W> {
         GIVec {
             allocator : index::Allocator::new(),
-            vec : Vec::new()
+            slots : Vec::with_capacity(capacity),
+            elements : Dense::with_capacity(capacity)
         }
     }
 
-    pub fn insert(&mut self, element : T) -> Index {
+    pub fn insert(&mut self, element : T) -> Handle<T, W> {
         let index = self.allocator.get();
-        if let Some(val) = self.vec.get_mut(index.index) {
-            *val = element;
-        }else {
-            self.vec.insert(index.index, element);
-        }
+        self.set(index, element);
+        Handle::new(index)
+    }
 
-        index
+    fn set(&mut self, index : Index<W>, element : T) {
+        if self.slots.len() <= index.index() {
+            self.slots.resize(index.index() + 1, None);
+        }
+        self.slots[index.index()] = Some(self.elements.push(index, element));
     }
 
-    pub fn get(&self, index : &Index) -> Option<&T> {
-        if !self.allocator.is_live(index){
+    fn position(&self, handle : &Handle<T, W>) -> Option<usize> {
+        let index = handle.raw();
+        if !self.allocator.is_live(&index) {
             None
-        }else{
-            self.vec.get(index.index)
+        } else {
+            self.slots.get(index.index()).cloned().and_then(|position| position)
         }
     }
 
-    pub fn get_mut(&mut self, index : &Index) -> Option<&mut T> {
-        if !self.allocator.is_live(index){
-            None
-        }else{
-            self.vec.get_mut(index.index)
+    //Reserved indices are live but empty until an element is inserted at them
+    pub fn reserve(&self) -> Handle<T, W> {
+        Handle::new(self.allocator.reserve())
+    }
+
+    //Returns false if the index wasn't reserved or already holds an element
+    pub fn insert_reserved(&mut self, handle : &Handle<T, W>, element : T) -> bool {
+        self.allocator.flush();
+        if !self.allocator.is_live(&handle.raw()) || self.position(handle).is_some() {
+            return false;
         }
+        self.set(handle.raw(), element);
+        true
+    }
+
+    pub fn allocator(&self) -> &index::Allocator<W> {
+        &self.allocator
+    }
+
+    //Drops every element and takes over the allocator, e.g. one that was saved earlier.
+    //Handles that were live in it can then be filled in again with insert_reserved.
+    pub fn restore_allocator(&mut self, allocator : index::Allocator<W>) {
+        self.slots.clear();
+        self.elements.clear();
+        self.allocator = allocator;
+    }
+
+    pub fn get(&self, handle : &Handle<T, W>) -> Option<&T> {
+        self.position(handle).map(|position| self.elements.get(position))
     }
 
-    pub fn remove(&mut self, index : Index) -> bool {
-        self.allocator.release(index)
+    pub fn get_mut(&mut self, handle : &Handle<T, W>) -> Option<&mut T> {
+        self.position(handle).map(move |position| self.elements.get_mut(position))
     }
-}
\ No newline at end of file
+
+    //Safety: no other reference to the handle's element may be alive
+    pub(crate) unsafe fn get_unchecked_mut<'a>(vec : *mut GIVec<T, W>, handle : &Handle<T, W>) -> Option<&'a mut T> {
+        let position = (*vec).position(handle)?;
+        Some(Dense::get_unchecked_mut(std::ptr::addr_of_mut!((*vec).elements), position))
+    }
+
+    pub fn remove(&mut self, handle : Handle<T, W>) -> Option<T> {
+        match self.position(&handle) {
+            Some(position) => Some(self.remove_at(position).1),
+            //a reserved index that never got an element is released all the same, even before it was flushed
+            None => {
+                self.allocator.flush();
+                self.allocator.release(handle.raw());
+                None
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    //Indices of the removed elements stay dead, they aren't reset
+    pub fn clear(&mut self) {
+        self.drain();
+    }
+
+    pub fn drain(&mut self) -> Drain<'_, T, W> {
+        Drain::new(self)
+    }
+
+    pub fn retain<F : FnMut(Handle<T, W>, &mut T) -> bool>(&mut self, keep : F) {
+        dense::retain(self, keep)
+    }
+
+    pub fn iter(&self) -> Iter<'_, T, W> {
+        self.elements.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, T, W> {
+        self.elements.iter_mut()
+    }
+}
+
+impl<T, W : IndexWidth> DenseContainer for GIVec<T, W> {
+    type Value = T;
+    type Raw = Index<W>;
+
+    fn dense_mut(&mut self) -> &mut Dense<T, Index<W>> {
+        &mut self.elements
+    }
+
+    //The index is released, so handles to the element stop resolving
+    fn remove_at(&mut self, position : usize) -> (Handle<T, W>, T) {
+        let (index, element) = self.elements.swap_remove(position);
+        self.slots[index.index()] = None;
+        if let Some(moved) = self.elements.owner(position) {
+            self.slots[moved.index()] = Some(position);
+        }
+        self.allocator.release(index);
+        (Handle::new(index), element)
+    }
+}
@@ -0,0 +1,68 @@
+use super::generational_index::{self, GIVec};
+use super::sparse_set::SparseSet;
+use super::Handle;
+
+//Every component in a GIVec of its type, found through the handle kept for its entity.
+//This is how components were stored before archetype tables, it is kept so the two can be benchmarked
+//against each other. Lookups go through the entity's handle and then the GIVec's own slots.
+pub struct GIVecStore<T> {
+    values: GIVec<T>,
+    handles: SparseSet<generational_index::Handle<T>>,
+}
+
+impl<T> GIVecStore<T> {
+    pub fn new() -> GIVecStore<T> {
+        GIVecStore {
+            values: GIVec::new(),
+            handles: SparseSet::new(),
+        }
+    }
+
+    pub fn contains(&self, entity: &Handle) -> bool {
+        self.handles.contains(entity)
+    }
+
+    //Replaces and returns the entity's previous value
+    pub fn insert(&mut self, entity: &Handle, value: T) -> Option<T> {
+        if let Some(handle) = self.handles.get(entity) {
+            let old = self.values.get_mut(handle).expect("entity holds a handle to a removed component");
+            return Some(std::mem::replace(old, value));
+        }
+        let handle = self.values.insert(value);
+        self.handles.insert(entity, handle);
+        None
+    }
+
+    pub fn remove(&mut self, entity: &Handle) -> Option<T> {
+        let handle = self.handles.remove(entity)?;
+        self.values.remove(handle)
+    }
+
+    pub fn get(&self, entity: &Handle) -> Option<&T> {
+        self.handles.get(entity).and_then(|handle| self.values.get(handle))
+    }
+
+    pub fn get_mut(&mut self, entity: &Handle) -> Option<&mut T> {
+        let handle = self.handles.get(entity)?;
+        self.values.get_mut(handle)
+    }
+
+    //Safety: no other reference to the entity's value may be alive
+    pub(super) unsafe fn get_unchecked_mut<'a>(store: *mut GIVecStore<T>, entity: &Handle) -> Option<&'a mut T> {
+        let handle = (*store).handles.get(entity)?;
+        GIVec::get_unchecked_mut(std::ptr::addr_of_mut!((*store).values), handle)
+    }
+
+    //The entities that have a component
+    pub fn entities(&self) -> &[Handle] {
+        self.handles.entities()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
@@ -0,0 +1,217 @@
+use super::system::{Access, System};
+use super::{Commands, Component, Ecs, Handle};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+//The entity this one is attached to, set with Ecs::set_parent
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Parent(pub Handle);
+impl Component for Parent {}
+
+//Kept in sync with the Parent components of the children by the Ecs
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Children(pub Vec<Handle>);
+impl Component for Children {}
+
+//Position relative to the parent, or to the world for entities without one.
+//Scale is uniform so a transform of a transform is still a transform.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Transform {
+    pub translation: [f32; 3],
+    //unit quaternion, x y z w
+    pub rotation: [f32; 4],
+    pub scale: f32,
+}
+impl Component for Transform {}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform {
+            translation: [0.0; 3],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+            scale: 1.0,
+        }
+    }
+}
+
+fn quat_mul(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    [
+        a[3] * b[0] + a[0] * b[3] + a[1] * b[2] - a[2] * b[1],
+        a[3] * b[1] - a[0] * b[2] + a[1] * b[3] + a[2] * b[0],
+        a[3] * b[2] + a[0] * b[1] - a[1] * b[0] + a[2] * b[3],
+        a[3] * b[3] - a[0] * b[0] - a[1] * b[1] - a[2] * b[2],
+    ]
+}
+
+//v + 2w(q x v) + 2(q x (q x v)), with q the vector part of the quaternion
+fn rotate(q: [f32; 4], v: [f32; 3]) -> [f32; 3] {
+    let cross = |a: [f32; 3], b: [f32; 3]| [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]];
+    let axis = [q[0], q[1], q[2]];
+    let t = cross(axis, v);
+    let t = [2.0 * t[0], 2.0 * t[1], 2.0 * t[2]];
+    let u = cross(axis, t);
+    [v[0] + q[3] * t[0] + u[0], v[1] + q[3] * t[1] + u[1], v[2] + q[3] * t[2] + u[2]]
+}
+
+impl Transform {
+    pub fn from_translation(translation: [f32; 3]) -> Transform {
+        Transform {
+            translation,
+            ..Transform::default()
+        }
+    }
+
+    //The child's transform as seen from where this one is relative to
+    pub fn mul_transform(&self, child: &Transform) -> Transform {
+        let offset = rotate(self.rotation, child.translation);
+        Transform {
+            translation: [
+                self.translation[0] + self.scale * offset[0],
+                self.translation[1] + self.scale * offset[1],
+                self.translation[2] + self.scale * offset[2],
+            ],
+            rotation: quat_mul(self.rotation, child.rotation),
+            scale: self.scale * child.scale,
+        }
+    }
+
+    pub fn transform_point(&self, point: [f32; 3]) -> [f32; 3] {
+        self.mul_transform(&Transform::from_translation(point)).translation
+    }
+}
+
+//Position in the world, written by TransformPropagation
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GlobalTransform(pub Transform);
+impl Component for GlobalTransform {}
+
+//Computes GlobalTransforms from the root entities down. A subtree is only recomputed when the Transform or
+//Parent of its root changed since the last run, entities that are missing a GlobalTransform get one.
+//Entities without a Transform count as the identity, so their children still follow the entity above them.
+//They don't have a GlobalTransform, one is removed along with the Transform.
+pub struct TransformPropagation;
+
+impl TransformPropagation {
+    fn propagate(ecs: &Ecs, commands: &mut Commands, handle: &Handle, parent: Option<&Transform>, parent_dirty: bool, removed: &HashSet<Handle>) {
+        let transform = ecs.get_comp::<Transform>(handle).map(|transform| *transform);
+        let dirty = parent_dirty
+            || ecs.is_changed::<Transform>(handle)
+            || ecs.is_changed::<Parent>(handle)
+            || removed.contains(handle);
+
+        let global = match (transform, parent) {
+            (Some(transform), Some(parent)) => parent.mul_transform(&transform),
+            (Some(transform), None) => transform,
+            (None, parent) => parent.cloned().unwrap_or_default(),
+        };
+        if transform.is_some() {
+            //only a dirty subtree is written, so Changed<GlobalTransform> means it really moved
+            if ecs.get_comp::<GlobalTransform>(handle).is_none() {
+                commands.insert(handle, GlobalTransform(global));
+            } else if dirty {
+                ecs.borrow_comp_mut::<GlobalTransform>(handle).expect("entity has a GlobalTransform").0 = global;
+            }
+        }
+
+        if let Some(children) = ecs.get_comp::<Children>(handle) {
+            for child in &children.0 {
+                Self::propagate(ecs, commands, child, Some(&global), dirty, removed);
+            }
+        }
+    }
+}
+
+impl System for TransformPropagation {
+    fn name(&self) -> &str {
+        "transform_propagation"
+    }
+
+    fn access(&self) -> Access {
+        Access::new()
+            .read::<Transform>()
+            .read::<Parent>()
+            .read::<Children>()
+            .write::<GlobalTransform>()
+    }
+
+    fn run(&mut self, ecs: &Ecs) {
+        //entities that lost their parent keep their transform, but it is relative to the world now.
+        //Entities that lost their transform move their children back to the identity.
+        let removed: HashSet<Handle> = ecs.removed::<Parent>().chain(ecs.removed::<Transform>()).cloned().collect();
+        let roots: Vec<Handle> = ecs.query::<(&Transform,)>().without::<Parent>().iter().map(|(handle, _)| handle)
+            .chain(ecs.query::<(&Children,)>().without::<Parent>().without::<Transform>().iter().map(|(handle, _)| handle))
+            .collect();
+
+        let mut commands = ecs.commands();
+        for root in &roots {
+            Self::propagate(ecs, &mut commands, root, None, false, &removed);
+        }
+        //a GlobalTransform left over from a removed Transform would keep the entity where it was
+        for (handle, _) in &mut ecs.query::<(&GlobalTransform,)>().without::<Transform>() {
+            commands.remove::<GlobalTransform>(&handle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::query::Changed;
+    use crate::ecs::system::{FnSystem, Schedule, Stage};
+
+    //GlobalTransforms that changed in the last frame, counted after propagation
+    struct ChangedCount(usize);
+
+    fn global(ecs: &Ecs, handle: &Handle) -> [f32; 3] {
+        ecs.get_comp::<GlobalTransform>(handle).unwrap().0.translation
+    }
+
+    #[test]
+    fn propagation() {
+        let mut ecs = Ecs::new();
+        ecs.insert_resource(ChangedCount(0));
+        let count = FnSystem::new("count", Access::new().read::<GlobalTransform>().write_resource::<ChangedCount>(), |ecs: &Ecs| {
+            let changed = ecs.query::<(&GlobalTransform,)>().filter::<Changed<GlobalTransform>>().iter().count();
+            ecs.resource_mut::<ChangedCount>().unwrap().0 = changed;
+        });
+        let mut schedule = Schedule::builder()
+            .add_system_to_stage(Stage::PostUpdate, TransformPropagation)
+            .add_system_to_stage(Stage::Render, count)
+            .build()
+            .unwrap();
+        let changed = |ecs: &Ecs| ecs.resource::<ChangedCount>().unwrap().0;
+
+        let root = ecs.spawn((Transform::from_translation([1.0, 0.0, 0.0]),));
+        //no Transform, counts as the identity
+        let group = ecs.create_entity();
+        let leaf = ecs.spawn((Transform::from_translation([0.0, 2.0, 0.0]),));
+        ecs.set_parent(&group, &root);
+        ecs.set_parent(&leaf, &group);
+
+        schedule.run(&mut ecs);
+        assert_eq!(global(&ecs, &leaf), [1.0, 2.0, 0.0]);
+        assert!(ecs.get_comp::<GlobalTransform>(&group).is_none());
+        assert_eq!(changed(&ecs), 2);
+
+        schedule.run(&mut ecs);
+        assert_eq!(changed(&ecs), 0);
+
+        ecs.get_comp_mut::<Transform>(&root).unwrap().translation = [5.0, 0.0, 0.0];
+        schedule.run(&mut ecs);
+        assert_eq!(global(&ecs, &leaf), [5.0, 2.0, 0.0]);
+        assert_eq!(changed(&ecs), 2);
+
+        ecs.remove_comp::<Transform>(&root);
+        schedule.run(&mut ecs);
+        assert_eq!(global(&ecs, &leaf), [0.0, 2.0, 0.0]);
+        assert!(ecs.get_comp::<GlobalTransform>(&root).is_none());
+
+        //without children it isn't reached from any root
+        let lone = ecs.spawn((Transform::from_translation([3.0, 0.0, 0.0]),));
+        schedule.run(&mut ecs);
+        assert_eq!(global(&ecs, &lone), [3.0, 0.0, 0.0]);
+        ecs.remove_comp::<Transform>(&lone);
+        schedule.run(&mut ecs);
+        assert!(ecs.get_comp::<GlobalTransform>(&lone).is_none());
+    }
+}
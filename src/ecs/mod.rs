@@ -1,96 +1,869 @@
 
-mod generational_index;
+pub mod generational_index;
 mod entity;
+mod archetype;
+mod sparse_set;
+mod givec_store;
+mod storage;
+mod cell;
+pub mod query;
+mod ticks;
+mod commands;
+mod bundle;
+pub mod hierarchy;
+pub mod scene;
+pub mod snapshot;
+pub mod events;
+pub mod system;
 
 
 use entity::Entity;
+use archetype::{Archetypes, Columns, ComponentInfo};
+use storage::Storage;
 use generational_index::GIVec;
-use typemap::{Key, TypeMap};
+use typemap::{Key, ShareMap};
+use cell::AtomicRefCell;
+use ticks::{ComponentTicks, CHECK_TICK_THRESHOLD};
+use commands::Command;
+use events::Events;
+use hierarchy::{Children, Parent};
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::sync::Mutex;
 
-type Handle = generational_index::Index;
+pub use cell::{Ref, RefMut};
 
-pub trait Component {}
+pub use query::{Fetch, Query};
+pub use ticks::RemovedComponents;
+pub use commands::Commands;
+pub use bundle::{Bundle, EntityMut};
+//#[derive(Bundle)] for structs whose fields are all components
+pub use rust_engine_derive::Bundle;
+pub use storage::StorageType;
+
+//Entity handles, only valid for the Ecs that created them
+pub type Handle = generational_index::Handle<Entity>;
+
+//Components are shared with systems running on other threads
+pub trait Component: Send + Sync {
+    //Components that are added and removed every frame can opt into a sparse set,
+    //so they don't move their entity between archetypes. StorageType::GIVec is the layout from before tables.
+    const STORAGE: StorageType = StorageType::Table;
+
+    //Hooks for components that own something outside of the Ecs, like a GPU mesh or an audio voice.
+    //They get the Ecs shared, structural changes have to go through ecs.commands().
+
+    //Runs before the component is stored on the entity, also when it replaces one of the same type.
+    //Components put back by SceneRegistry::restore skip it, and the ones they replace skip on_remove.
+    fn on_add(&mut self, _ecs: &Ecs, _entity: &Handle) {}
+    //Runs on the component a new one replaced, once the new one is stored
+    fn on_replace(&mut self, _ecs: &Ecs, _entity: &Handle) {}
+    //Runs once the component was taken off the entity. When the entity is deleted, it can still be looked up
+    //with the components whose hooks haven't run yet, but queries skip it.
+    fn on_remove(&mut self, _ecs: &Ecs, _entity: &Handle) {}
+}
+
+//Global data that doesn't belong to an entity, like delta time or input state
+pub trait Resource: Send + Sync + 'static {}
+impl<T: Send + Sync + 'static> Resource for T {}
 
 //Key registry for typemaps
 struct ComponentRegister<T: Component>(std::marker::PhantomData<T>);
 impl<T: Component + 'static> Key for ComponentRegister<T> {
-    type Value = GIVec<T>;
+    type Value = AtomicRefCell<Storage<T>>;
 }
 
-struct ComponentEntry<T: Component>(std::marker::PhantomData<T>);
-impl<T: Component + 'static> Key for ComponentEntry<T> {
-    type Value = Handle;
+struct ResourceRegister<R: Resource>(std::marker::PhantomData<R>);
+impl<R: Resource> Key for ResourceRegister<R> {
+    type Value = AtomicRefCell<R>;
 }
 
+#[derive(Fail, Debug)]
+#[fail(display = "entity doesn't exist")]
+pub struct NoSuchEntity;
+
 //ECS
 pub struct Ecs {
-    components: TypeMap,
+    components: ShareMap,
+    component_info: HashMap<TypeId, ComponentInfo>,
+    //registered types kept by entity rather than in tables, deleting an entity only has to look in these
+    sparse_types: Vec<TypeId>,
+    archetypes: Archetypes,
+    resources: ShareMap,
     entities: GIVec<Entity>,
+    ticks: HashMap<TypeId, ComponentTicks>,
+    //entities that lost a component and the tick they lost it at. Schedules drop what every system saw,
+    //without one they grow until clear_trackers, or until they get older than ticks::MAX_CHANGE_AGE.
+    removed: HashMap<TypeId, Vec<(Handle, u32)>>,
+    //changes are stamped with change_tick, Added and Changed match what is newer than last_change_tick.
+    //Ticks wrap around, see ticks::is_newer.
+    change_tick: u32,
+    last_change_tick: u32,
+    //when old ticks were last clamped
+    last_check_tick: u32,
+    //recorded by Commands, waiting for apply_commands
+    commands: Mutex<Vec<Command>>,
+    //swap the buffers of every event channel at the end of a frame
+    event_updates: Vec<fn(&Ecs)>,
 }
 
 impl Ecs {
     pub fn new() -> Ecs {
         Ecs {
-            components: TypeMap::new(),
+            components: ShareMap::custom(),
+            component_info: HashMap::new(),
+            sparse_types: Vec::new(),
+            archetypes: Archetypes::new(),
+            resources: ShareMap::custom(),
             entities: GIVec::new(),
+            ticks: HashMap::new(),
+            removed: HashMap::new(),
+            change_tick: 1,
+            last_change_tick: 0,
+            last_check_tick: 0,
+            commands: Mutex::new(Vec::new()),
+            event_updates: Vec::new(),
         }
     }
 
     fn register_type<T: Component + 'static>(&mut self) {
-        self.components.insert::<ComponentRegister<T>>(GIVec::new());
+        if !self.component_info.contains_key(&TypeId::of::<T>()) {
+            self.components.insert::<ComponentRegister<T>>(AtomicRefCell::new(Storage::new(T::STORAGE)));
+            self.component_info.insert(TypeId::of::<T>(), ComponentInfo::of::<T>());
+            self.ticks.insert(TypeId::of::<T>(), ComponentTicks::new());
+            if T::STORAGE != StorageType::Table {
+                self.sparse_types.push(TypeId::of::<T>());
+            }
+        }
     }
 
-    fn create_comp<T: Component + 'static>(&mut self, comp: T) -> Option<Handle> {
-        if let Some(store) = self.components.get_mut::<ComponentRegister<T>>() {
-            Some(store.insert(comp))
+    fn comp_added(&mut self, ty: TypeId, handle: &Handle, replaced: bool) {
+        let ticks = self.ticks.get_mut(&ty).expect("component type isn't registered");
+        if replaced {
+            ticks.set_changed(handle, self.change_tick);
         } else {
+            ticks.insert(handle, self.change_tick);
+        }
+    }
+
+    fn comp_removed(&mut self, ty: TypeId, handle: &Handle) {
+        self.ticks.get_mut(&ty).expect("component type isn't registered").remove(handle);
+        self.removed.entry(ty).or_default().push((*handle, self.change_tick));
+    }
+
+    //Starts a system run, changes it makes are newer than last_run.
+    //Returns the tick to pass as last_run the next time the system runs.
+    fn start_run(&mut self, last_run: u32) -> u32 {
+        self.change_tick = ticks::next_tick(self.change_tick);
+        self.last_change_tick = last_run;
+        self.change_tick
+    }
+
+    //Forgets removals every system has seen, changes made between frames are newer than any system run.
+    //Events are swapped separately by update_events.
+    fn end_frame(&mut self, oldest_run: u32) {
+        let change_tick = self.change_tick;
+        for removed in self.removed.values_mut() {
+            removed.retain(|(_, tick)| ticks::is_newer(*tick, oldest_run, change_tick));
+        }
+        self.check_ticks();
+        self.start_run(oldest_run);
+    }
+
+    //Drops the events of the previous frame in every channel
+    fn update_events(&mut self) {
+        for update in &self.event_updates {
+            update(self);
+        }
+    }
+
+    //Clamps ticks that are about to get old enough to wrap around, every CHECK_TICK_THRESHOLD ticks
+    fn check_ticks(&mut self) {
+        let change_tick = self.change_tick;
+        if change_tick.wrapping_sub(self.last_check_tick) < CHECK_TICK_THRESHOLD {
+            return;
+        }
+
+        for ticks in self.ticks.values_mut() {
+            ticks.check(change_tick);
+        }
+        //no system can see removals that old anymore
+        for removed in self.removed.values_mut() {
+            removed.retain_mut(|(_, tick)| ticks::check_tick(tick, change_tick));
+        }
+        self.last_check_tick = change_tick;
+    }
+
+    fn storage_mut<T: Component + 'static>(&mut self) -> &mut Storage<T> {
+        self.components.get_mut::<ComponentRegister<T>>().expect("component type isn't registered").get_mut()
+    }
+
+    fn columns_mut<T: Component + 'static>(&mut self) -> &mut Columns<T> {
+        self.storage_mut::<T>().columns_mut()
+    }
+
+    //Takes the entity's row out of its archetype's table, the entity in the last row takes its place
+    fn remove_row(&mut self, entity: Entity) {
+        let entities = &mut self.archetypes.get_mut(entity.archetype).entities;
+        entities.swap_remove(entity.row);
+        if let Some(moved) = entities.get(entity.row) {
+            self.entities.get_mut(moved).expect("archetype holds a dead entity").row = entity.row;
+        }
+    }
+
+    //Swaps the entity into the last row of its table and takes it out of the table's entity list, so queries
+    //skip it. Its components stay at the ends of the columns for delete_entity to pop off.
+    fn detach_row(&mut self, handle: &Handle, entity: Entity) {
+        let last = self.archetypes.get(entity.archetype).len() - 1;
+        if entity.row != last {
+            for ty in self.archetypes.get(entity.archetype).types() {
+                (self.component_info[ty].swap_rows)(&mut self.components, entity.archetype, entity.row, last);
+            }
+            let entities = &mut self.archetypes.get_mut(entity.archetype).entities;
+            entities.swap(entity.row, last);
+            let moved = entities[entity.row];
+            self.entities.get_mut(&moved).expect("archetype holds a dead entity").row = entity.row;
+            self.entities.get_mut(handle).expect("entity is alive").row = last;
+        }
+        self.archetypes.get_mut(entity.archetype).entities.pop();
+    }
+
+    //Moves the components the two archetypes share to the end of the target table.
+    //Components that aren't part of the target must already have been taken out.
+    fn move_entity(&mut self, handle: &Handle, entity: Entity, to: usize) {
+        for ty in self.archetypes.get(entity.archetype).types() {
+            if self.archetypes.get(to).has(*ty) {
+                (self.component_info[ty].move_row)(&mut self.components, entity.archetype, entity.row, to);
+            }
+        }
+        self.remove_row(entity);
+
+        let target = self.archetypes.get_mut(to);
+        let row = target.len();
+        target.entities.push(*handle);
+        *self.entities.get_mut(handle).unwrap() = Entity { archetype: to, row };
+    }
+
+
+    //Puts one component of a bundle into place after the entity moved to its new archetype,
+    //returns the component it replaced
+    fn put_comp<T: Component + 'static>(&mut self, handle: &Handle, from: usize, comp: T) -> Option<T> {
+        let ty = TypeId::of::<T>();
+        if T::STORAGE != StorageType::Table {
+            let old = self.storage_mut::<T>().insert_by_entity(handle, comp);
+            self.comp_added(ty, handle, old.is_some());
+            return old;
+        }
+
+        let entity = *self.entities.get(handle).expect("bundle inserted into a dead entity");
+        let replaced = self.archetypes.get(from).has(ty);
+        let column = self.columns_mut::<T>().column_mut(entity.archetype);
+        if replaced {
+            let old = std::mem::replace(&mut column[entity.row], comp);
+            self.comp_added(ty, handle, true);
+            Some(old)
+        } else {
+            column.push(comp);
+            self.comp_added(ty, handle, false);
             None
         }
     }
 
+    fn spawn_reserved(&mut self, handle: &Handle) {
+        let row = self.archetypes.get(Archetypes::EMPTY).len();
+        if self.entities.insert_reserved(handle, Entity { archetype: Archetypes::EMPTY, row }) {
+            self.archetypes.get_mut(Archetypes::EMPTY).entities.push(*handle);
+        }
+    }
+
 
     //PUBLIC METHODS
     pub fn create_entity(&mut self) -> Handle {
-        self.entities.insert(Entity::new())
+        let row = self.archetypes.get(Archetypes::EMPTY).len();
+        let handle = self.entities.insert(Entity { archetype: Archetypes::EMPTY, row });
+        self.archetypes.get_mut(Archetypes::EMPTY).entities.push(handle);
+        handle
     }
 
+    //Replaces the components the entity already has
+    pub fn insert_bundle<B: Bundle>(&mut self, handle: &Handle, mut bundle: B) -> Result<(), NoSuchEntity> {
+        let entity = *self.entities.get(handle).ok_or(NoSuchEntity)?;
+        let types = B::register(self);
+        bundle.on_add(self, handle);
+
+        let mut to = entity.archetype;
+        for (i, ty) in types.iter().enumerate() {
+            assert!(!types[..i].contains(ty), "bundle contains the same component type twice");
+            if self.component_info[ty].storage == StorageType::Table && !self.archetypes.get(to).has(*ty) {
+                to = self.archetypes.with(to, *ty);
+            }
+        }
+
+        if to != entity.archetype {
+            self.move_entity(handle, entity, to);
+        }
+        bundle.put(self, handle, entity.archetype);
+        Ok(())
+    }
+
+    pub fn spawn<B: Bundle>(&mut self, bundle: B) -> Handle {
+        let handle = self.create_entity();
+        self.insert_bundle(&handle, bundle).expect("entity was just created");
+        handle
+    }
+
+    pub fn entity(&mut self, handle: &Handle) -> Result<EntityMut<'_>, NoSuchEntity> {
+        if !self.entity_exists(handle) {
+            return Err(NoSuchEntity);
+        }
+        Ok(EntityMut::new(self, *handle))
+    }
+
+    pub fn commands(&self) -> Commands<'_> {
+        Commands::new(self)
+    }
+
+    //Runs everything recorded by Commands since the last call.
+    //The commands get a tick of their own so the systems that recorded them see their changes next time.
+    pub fn apply_commands(&mut self) {
+        let commands = std::mem::take(self.commands.get_mut().unwrap());
+        if commands.is_empty() {
+            return;
+        }
+
+        self.change_tick = ticks::next_tick(self.change_tick);
+        for command in commands {
+            command(self);
+        }
+    }
+
+    //Drops all components of the entity along with it, running their on_remove hooks.
+    //Its children lose their Parent and become roots, see despawn_recursive to delete them as well.
     pub fn delete_entity(&mut self, handle : Handle) -> bool {
-        self.entities.remove(handle)
+        if !self.entity_exists(&handle) {
+            //a reserved handle that never got an entity is released all the same
+            self.entities.remove(handle);
+            return false;
+        }
+
+        self.remove_parent(&handle);
+        let children = self.get_comp_mut::<Children>(&handle).map(|children| std::mem::take(&mut children.0));
+        for child in children.unwrap_or_default() {
+            self.remove_comp::<Parent>(&child);
+        }
+
+        //components are taken off one at a time and each hook runs on its own, the entity keeps the rest meanwhile
+        let entity = *self.entities.get(&handle).expect("entity was checked above");
+        self.detach_row(&handle, entity);
+        let mut types = self.archetypes.get(entity.archetype).types().to_vec();
+        for ty in &types {
+            (self.component_info[ty].take_last)(self, entity.archetype, &handle);
+        }
+        //hooks can't register types, so the list stays the same
+        for i in 0..self.sparse_types.len() {
+            let ty = self.sparse_types[i];
+            if (self.component_info[&ty].take_sparse)(self, &handle) {
+                types.push(ty);
+            }
+        }
+
+        self.entities.remove(handle);
+        for ty in types {
+            self.comp_removed(ty, &handle);
+        }
+        true
     }
 
-    pub fn get_comp<T: Component + 'static>(&self, index: &Handle) -> Option<&T> {
-        self.components.get::<ComponentRegister<T>>().and_then(|store| {
-            store.get(index)
-        })
+    //Deletes the entity and all of its descendants
+    pub fn despawn_recursive(&mut self, handle: Handle) -> bool {
+        let children = self.get_comp::<Children>(&handle).map(|children| children.0.clone());
+        for child in children.unwrap_or_default() {
+            self.despawn_recursive(child);
+        }
+        self.delete_entity(handle)
+    }
+
+    pub fn iter_entities(&self) -> impl Iterator<Item = Handle> + '_ {
+        self.entities.iter().map(|(handle, _)| handle)
     }
 
-    pub fn add_comp<T: Component + 'static>(&mut self, entity: &Handle, comp: T) -> bool {
-        if !self.components.contains::<ComponentRegister<T>>() {
-            self.register_type::<T>();
+    pub fn entity_exists(&self, handle: &Handle) -> bool {
+        self.entities.get(handle).is_some()
+    }
+
+    //Returns false if either entity doesn't exist or the parent is a descendant of the child
+    pub fn set_parent(&mut self, child: &Handle, parent: &Handle) -> bool {
+        if !self.entity_exists(child) || !self.entity_exists(parent) {
+            return false;
         }
 
-        let index = self.create_comp(comp).unwrap();
+        let mut ancestor = Some(*parent);
+        while let Some(handle) = ancestor {
+            if handle == *child {
+                return false;
+            }
+            ancestor = self.get_comp::<Parent>(&handle).map(|parent| parent.0);
+        }
 
-        if let Some(entity) = self.entities.get_mut(entity) {
-            if let None = entity.0.insert::<ComponentEntry<T>>(index) {
+        self.remove_parent(child);
+        self.add_comp(child, Parent(*parent)).expect("entity was checked above");
+        match self.get_comp_mut::<Children>(parent) {
+            Some(children) => children.0.push(*child),
+            None => {
+                self.add_comp(parent, Children(vec![*child])).expect("entity was checked above");
+            }
+        }
+        true
+    }
+
+    //The entity becomes a root, returns false if it had no parent
+    pub fn remove_parent(&mut self, child: &Handle) -> bool {
+        match self.remove_comp::<Parent>(child) {
+            Some(Parent(parent)) => {
+                if let Some(children) = self.get_comp_mut::<Children>(&parent) {
+                    children.0.retain(|handle| handle != child);
+                }
                 true
-            } else {
-                false
             }
-        } else {
-            false
+            None => false,
         }
     }
 
-    pub fn remove_comp<T: Component + 'static>(&mut self, entity: &Handle) -> bool {
-        let comp_handle = self.entities.get_mut(entity).and_then(|x| {
-            x.0.remove::<ComponentEntry<T>>()
+    pub fn get_comp<T: Component + 'static>(&self, handle: &Handle) -> Option<Ref<'_, T>> {
+        let entity = self.entities.get(handle)?;
+        let storage = self.components.get::<ComponentRegister<T>>()?.borrow();
+        Ref::filter_map(storage, |storage| storage.get(entity, handle)).ok()
+    }
+
+    //Marks the component changed
+    pub fn get_comp_mut<T: Component + 'static>(&mut self, handle: &Handle) -> Option<&mut T> {
+        let entity = self.entities.get(handle)?;
+        let storage = self.components.get_mut::<ComponentRegister<T>>()?.get_mut();
+        let comp = storage.get_mut(entity, handle)?;
+        self.ticks[&TypeId::of::<T>()].set_changed(handle, self.change_tick);
+        Some(comp)
+    }
+
+    //Like get_comp_mut through a shared Ecs, the store is borrowed at runtime like a resource
+    pub fn borrow_comp_mut<T: Component + 'static>(&self, handle: &Handle) -> Option<RefMut<'_, T>> {
+        let entity = self.entities.get(handle)?;
+        let storage = self.components.get::<ComponentRegister<T>>()?.try_borrow_mut().unwrap_or_else(|_| {
+            panic!("can't mutably borrow {}, it is already borrowed", std::any::type_name::<T>())
         });
+        let comp = RefMut::filter_map(storage, |storage| storage.get_mut(entity, handle)).ok()?;
+        self.ticks[&TypeId::of::<T>()].set_changed(handle, self.change_tick);
+        Some(comp)
+    }
+
+    //Whether the entity's T was added since the running system last ran
+    pub fn is_added<T: Component + 'static>(&self, handle: &Handle) -> bool {
+        //ticks are kept per slot, a stale handle would see the entity that took its slot over
+        self.entity_exists(handle) && self.ticks.get(&TypeId::of::<T>()).is_some_and(|ticks| {
+            ticks.contains(handle) && ticks::is_newer(ticks.added(handle), self.last_change_tick, self.change_tick)
+        })
+    }
+
+    //Whether the entity's T was added or mutably accessed since the running system last ran
+    pub fn is_changed<T: Component + 'static>(&self, handle: &Handle) -> bool {
+        self.entity_exists(handle) && self.ticks.get(&TypeId::of::<T>()).is_some_and(|ticks| {
+            ticks.contains(handle) && ticks::is_newer(ticks.changed(handle), self.last_change_tick, self.change_tick)
+        })
+    }
+
+    pub fn query<Q: Fetch>(&self) -> Query<'_, Q> {
+        Query::new(self)
+    }
+
+    //Removals are kept until every system of the Schedule saw them. Code that uses the Ecs without a
+    //Schedule has to call clear_trackers, otherwise they pile up for a long time.
+    pub fn removed<T: Component + 'static>(&self) -> RemovedComponents<'_, T> {
+        let removed = self.removed.get(&TypeId::of::<T>()).map_or(&[][..], |removed| &removed[..]);
+        RemovedComponents::new(removed, self.last_change_tick, self.change_tick)
+    }
+
+    //Added, Changed and removed() only see what happened after the last call.
+    //Schedules track this per system, this is for code that uses the Ecs without one.
+    pub fn clear_trackers(&mut self) {
+        self.last_change_tick = self.change_tick;
+        self.change_tick = ticks::next_tick(self.change_tick);
+        self.removed.clear();
+        self.check_ticks();
+    }
+
+    //Returns the previous value if the resource was already present
+    pub fn insert_resource<R: Resource>(&mut self, resource: R) -> Option<R> {
+        self.resources
+            .insert::<ResourceRegister<R>>(AtomicRefCell::new(resource))
+            .map(AtomicRefCell::into_inner)
+    }
+
+    //Adds the Events<E> resource if it isn't there yet, its events are dropped after two frames
+    pub fn add_event<E: Resource>(&mut self) {
+        if self.resource::<Events<E>>().is_none() {
+            self.insert_resource(Events::<E>::new());
+            self.event_updates.push(events::update_events::<E>);
+        }
+    }
 
-        let comp_handle = match comp_handle {None => {return false}, Some(val) => val };
+    //Returns false if the channel wasn't added
+    pub fn send_event<E: Resource>(&self, event: E) -> bool {
+        match self.resource_mut::<Events<E>>() {
+            Some(mut events) => {
+                events.send(event);
+                true
+            }
+            None => false,
+        }
+    }
 
-        let register = self.components.get_mut::<ComponentRegister<T>>().expect("found component handle, but component doesn't exist");
+    pub fn remove_resource<R: Resource>(&mut self) -> Option<R> {
+        self.resources.remove::<ResourceRegister<R>>().map(AtomicRefCell::into_inner)
+    }
 
-        register.remove(comp_handle)
+    //Resources are borrowed like component stores, a conflicting borrow panics
+    pub fn resource<R: Resource>(&self) -> Option<Ref<'_, R>> {
+        self.resources.get::<ResourceRegister<R>>().map(|resource| {
+            resource.try_borrow().unwrap_or_else(|_| {
+                panic!("can't borrow resource {}, it is already mutably borrowed", std::any::type_name::<R>())
+            })
+        })
     }
-}
\ No newline at end of file
+
+    pub fn resource_mut<R: Resource>(&self) -> Option<RefMut<'_, R>> {
+        self.resources.get::<ResourceRegister<R>>().map(|resource| {
+            resource.try_borrow_mut().unwrap_or_else(|_| {
+                panic!("can't mutably borrow resource {}, it is already borrowed", std::any::type_name::<R>())
+            })
+        })
+    }
+
+    //Replaces and returns the component if the entity already had one of this type,
+    //otherwise the entity moves to the archetype that includes the new type
+    pub fn add_comp<T: Component + 'static>(&mut self, handle: &Handle, mut comp: T) -> Result<Option<T>, NoSuchEntity> {
+        let entity = *self.entities.get(handle).ok_or(NoSuchEntity)?;
+        self.register_type::<T>();
+        comp.on_add(self, handle);
+
+        let ty = TypeId::of::<T>();
+        if T::STORAGE != StorageType::Table {
+            let mut old = self.storage_mut::<T>().insert_by_entity(handle, comp);
+            self.comp_added(ty, handle, old.is_some());
+            if let Some(old) = &mut old {
+                old.on_replace(self, handle);
+            }
+            return Ok(old);
+        }
+
+        if self.archetypes.get(entity.archetype).has(ty) {
+            let old = self.columns_mut::<T>().get_mut(entity.archetype, entity.row).expect("archetype is missing a column");
+            let mut old = std::mem::replace(old, comp);
+            self.comp_added(ty, handle, true);
+            old.on_replace(self, handle);
+            return Ok(Some(old));
+        }
+
+        let to = self.archetypes.with(entity.archetype, ty);
+        self.move_entity(handle, entity, to);
+        self.columns_mut::<T>().column_mut(to).push(comp);
+        self.comp_added(ty, handle, false);
+        Ok(None)
+    }
+
+    pub fn remove_comp<T: Component + 'static>(&mut self, handle: &Handle) -> Option<T> {
+        let entity = *self.entities.get(handle)?;
+        let ty = TypeId::of::<T>();
+        if T::STORAGE != StorageType::Table {
+            let mut comp = self.components.get_mut::<ComponentRegister<T>>()?.get_mut().remove_by_entity(handle)?;
+            self.comp_removed(ty, handle);
+            comp.on_remove(self, handle);
+            return Some(comp);
+        }
+
+        if !self.archetypes.get(entity.archetype).has(ty) {
+            return None;
+        }
+
+        let mut comp = self.columns_mut::<T>().column_mut(entity.archetype).swap_remove(entity.row);
+        let to = self.archetypes.without(entity.archetype, ty);
+        self.move_entity(handle, entity, to);
+        self.comp_removed(ty, handle);
+        comp.on_remove(self, handle);
+        Some(comp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Pos(i32);
+    impl Component for Pos {}
+
+    #[derive(Debug, PartialEq)]
+    struct Stunned(u32);
+    impl Component for Stunned {
+        const STORAGE: StorageType = StorageType::SparseSet;
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Vel(i32);
+    impl Component for Vel {
+        const STORAGE: StorageType = StorageType::GIVec;
+    }
+
+    #[test]
+    fn add_and_remove_on_dead_entity() {
+        let mut ecs = Ecs::new();
+        let entity = ecs.create_entity();
+        assert!(ecs.delete_entity(entity));
+
+        assert!(ecs.add_comp(&entity, Pos(1)).is_err());
+        assert!(ecs.remove_comp::<Pos>(&entity).is_none());
+        assert!(ecs.get_comp::<Pos>(&entity).is_none());
+    }
+
+    #[test]
+    fn stale_handle_after_reuse() {
+        let mut ecs = Ecs::new();
+        let old = ecs.create_entity();
+        ecs.add_comp(&old, Pos(1)).unwrap();
+        ecs.delete_entity(old);
+
+        let new = ecs.create_entity();
+        assert_eq!(old.index(), new.index());
+        ecs.add_comp(&new, Pos(2)).unwrap();
+
+        assert!(!ecs.entity_exists(&old));
+        assert!(ecs.entity_exists(&new));
+        assert!(ecs.get_comp::<Pos>(&old).is_none());
+        assert!(ecs.add_comp(&old, Pos(3)).is_err());
+        assert!(ecs.remove_comp::<Pos>(&old).is_none());
+        assert!(!ecs.delete_entity(old));
+        assert_eq!(*ecs.get_comp::<Pos>(&new).unwrap(), Pos(2));
+        assert!(ecs.is_added::<Pos>(&new));
+        assert!(!ecs.is_added::<Pos>(&old));
+        assert!(!ecs.is_changed::<Pos>(&old));
+    }
+
+    #[test]
+    fn double_delete() {
+        let mut ecs = Ecs::new();
+        let entity = ecs.create_entity();
+        ecs.add_comp(&entity, Pos(1)).unwrap();
+
+        assert!(ecs.delete_entity(entity));
+        assert!(!ecs.delete_entity(entity));
+        assert!(!ecs.entity_exists(&entity));
+        assert_eq!(ecs.query::<(&Pos,)>().iter().count(), 0);
+    }
+
+    #[test]
+    fn add_comp_replaces() {
+        let mut ecs = Ecs::new();
+        let entity = ecs.create_entity();
+
+        assert_eq!(ecs.add_comp(&entity, Pos(1)).unwrap(), None);
+        assert_eq!(ecs.add_comp(&entity, Pos(2)).unwrap(), Some(Pos(1)));
+        assert_eq!(*ecs.get_comp::<Pos>(&entity).unwrap(), Pos(2));
+        assert_eq!(ecs.remove_comp::<Pos>(&entity), Some(Pos(2)));
+        assert_eq!(ecs.remove_comp::<Pos>(&entity), None);
+    }
+
+    #[test]
+    fn sparse_only_query() {
+        let mut ecs = Ecs::new();
+        let entities: Vec<Handle> = (0..10).map(|_| ecs.create_entity()).collect();
+        for (i, entity) in entities.iter().enumerate() {
+            ecs.add_comp(entity, Pos(i as i32)).unwrap();
+            if i % 3 == 0 {
+                ecs.add_comp(entity, Stunned(i as u32)).unwrap();
+            }
+        }
+        ecs.delete_entity(entities[3]);
+
+        for (_, (stunned,)) in &mut ecs.query::<(&mut Stunned,)>() {
+            stunned.0 += 100;
+        }
+        let mut stunned: Vec<u32> = ecs.query::<(&Stunned,)>().iter().map(|(_, (stunned,))| stunned.0).collect();
+        stunned.sort();
+        assert_eq!(stunned, vec![100, 106, 109]);
+
+        let with_pos = ecs.query::<(&Stunned,)>().with::<Pos>().iter().count();
+        let without_pos = ecs.query::<(&Stunned,)>().without::<Pos>().iter().count();
+        assert_eq!((with_pos, without_pos), (3, 0));
+        assert_eq!(ecs.query::<(&Pos, &Stunned)>().iter().count(), 3);
+    }
+
+    #[test]
+    fn givec_storage() {
+        let mut ecs = Ecs::new();
+        let entities: Vec<Handle> = (0..6).map(|i| ecs.spawn((Pos(i),))).collect();
+        for (i, entity) in entities.iter().enumerate().filter(|(i, _)| i % 2 == 0) {
+            assert!(ecs.add_comp(entity, Vel(i as i32)).unwrap().is_none());
+        }
+        assert_eq!(ecs.add_comp(&entities[2], Vel(20)).unwrap(), Some(Vel(2)));
+        assert_eq!(*ecs.get_comp::<Vel>(&entities[2]).unwrap(), Vel(20));
+        assert!(ecs.get_comp::<Vel>(&entities[1]).is_none());
+        //the entities stay in the archetype of their table components
+        assert_eq!(ecs.archetypes.get(ecs.entities.get(&entities[0]).unwrap().archetype).types(), &[TypeId::of::<Pos>()]);
+
+        for (_, (pos, vel)) in &mut ecs.query::<(&mut Pos, &Vel)>() {
+            pos.0 += vel.0;
+        }
+        assert_eq!(*ecs.get_comp::<Pos>(&entities[2]).unwrap(), Pos(22));
+        assert_eq!(*ecs.get_comp::<Pos>(&entities[4]).unwrap(), Pos(8));
+
+        assert_eq!(ecs.remove_comp::<Vel>(&entities[0]), Some(Vel(0)));
+        assert!(ecs.delete_entity(entities[4]));
+        assert_eq!(ecs.removed::<Vel>().count(), 2);
+        let vels: Vec<(Handle, i32)> = ecs.query::<(&Vel,)>().iter().map(|(handle, (vel,))| (handle, vel.0)).collect();
+        assert_eq!(vels, vec![(entities[2], 20)]);
+        assert_eq!(ecs.query::<(&Pos,)>().with::<Vel>().iter().count(), 1);
+        assert_eq!(ecs.query::<(&Pos,)>().without::<Vel>().iter().count(), 4);
+
+        //a new entity in the freed slot doesn't see the old one's component
+        let reused = ecs.spawn((Pos(9),));
+        assert_eq!(reused.index(), entities[4].index());
+        assert!(ecs.get_comp::<Vel>(&reused).is_none());
+        ecs.add_comp(&reused, Vel(1)).unwrap();
+        assert_eq!(*ecs.get_comp::<Vel>(&reused).unwrap(), Vel(1));
+        assert!(ecs.get_comp::<Vel>(&entities[4]).is_none());
+    }
+
+    #[test]
+    fn change_ticks_wrap_around() {
+        let mut ecs = Ecs::new();
+        ecs.change_tick = u32::MAX - 1;
+        ecs.last_check_tick = ecs.change_tick;
+        let entity = ecs.create_entity();
+        ecs.add_comp(&entity, Pos(1)).unwrap();
+        assert!(ecs.is_added::<Pos>(&entity));
+
+        ecs.clear_trackers();
+        assert!(!ecs.is_changed::<Pos>(&entity));
+        ecs.clear_trackers();
+        assert_eq!(ecs.change_tick, 1);
+
+        ecs.get_comp_mut::<Pos>(&entity).unwrap().0 = 2;
+        assert!(ecs.is_changed::<Pos>(&entity));
+        assert!(!ecs.is_added::<Pos>(&entity));
+        assert_eq!(ecs.query::<(&Pos,)>().filter::<query::Changed<Pos>>().iter().count(), 1);
+
+        //a whole turn of the counter later, the change would look a few ticks old if it wasn't clamped
+        for _ in 0..4 {
+            ecs.change_tick = ecs.change_tick.wrapping_add(CHECK_TICK_THRESHOLD);
+            ecs.clear_trackers();
+        }
+        ecs.last_change_tick = ecs.change_tick.wrapping_sub(10);
+        assert!(!ecs.is_changed::<Pos>(&entity));
+        assert_eq!(ecs.query::<(&Pos,)>().filter::<query::Changed<Pos>>().iter().count(), 0);
+    }
+
+    #[test]
+    fn reserved_handles_are_valid_once_applied() {
+        let mut ecs = Ecs::new();
+        let (first, second) = {
+            let mut commands = ecs.commands();
+            let first = commands.spawn((Pos(1),));
+            let second = commands.spawn((Pos(2),));
+            commands.insert(&second, Stunned(2));
+            (first, second)
+        };
+        assert_ne!(first.index(), second.index());
+        assert!(!ecs.entity_exists(&first));
+
+        ecs.apply_commands();
+        assert_eq!(*ecs.get_comp::<Pos>(&first).unwrap(), Pos(1));
+        assert_eq!(*ecs.get_comp::<Pos>(&second).unwrap(), Pos(2));
+        assert_eq!(*ecs.get_comp::<Stunned>(&second).unwrap(), Stunned(2));
+    }
+
+    #[test]
+    fn create_entity_between_reserve_and_apply() {
+        let mut ecs = Ecs::new();
+        let reserved = ecs.commands().spawn((Pos(1),));
+        //flushes the reservation, the slot is live but holds no entity yet
+        let created = ecs.create_entity();
+        assert_ne!(reserved.index(), created.index());
+        assert!(!ecs.entity_exists(&reserved));
+        assert_eq!(ecs.iter_entities().count(), 1);
+
+        ecs.apply_commands();
+        assert_eq!(*ecs.get_comp::<Pos>(&reserved).unwrap(), Pos(1));
+        assert!(ecs.get_comp::<Pos>(&created).is_none());
+        assert_eq!(ecs.iter_entities().count(), 2);
+    }
+
+    #[test]
+    fn despawn_reserved_before_apply() {
+        for flushed in [false, true].iter() {
+            let mut ecs = Ecs::new();
+            let reserved = ecs.commands().spawn((Pos(1),));
+            if *flushed {
+                ecs.create_entity();
+            }
+            assert!(!ecs.delete_entity(reserved));
+
+            //the spawn is skipped like commands for any other entity that is gone
+            ecs.apply_commands();
+            assert!(!ecs.entity_exists(&reserved));
+            assert_eq!(ecs.query::<(&Pos,)>().iter().count(), 0);
+
+            let reused = ecs.create_entity();
+            assert_eq!(reused.index(), reserved.index());
+            assert!(!ecs.entity_exists(&reserved));
+        }
+    }
+
+    //Sums up the value of the linked entity's Link and what queries see when it is removed
+    struct Link(Option<Handle>, i32);
+    impl Component for Link {
+        fn on_remove(&mut self, ecs: &Ecs, entity: &Handle) {
+            assert!(ecs.get_comp::<Link>(entity).is_none());
+            let linked = self.0.and_then(|linked| ecs.get_comp::<Link>(&linked).map(|link| link.1)).unwrap_or(0);
+            let seen = ecs.query::<(&Link,)>().iter().count() * 100 + ecs.query::<(&Stunned,)>().iter().count() * 1000;
+            *ecs.resource_mut::<i32>().unwrap() += linked + seen as i32;
+        }
+    }
+
+    #[test]
+    fn delete_hook_reads_own_type() {
+        let mut ecs = Ecs::new();
+        ecs.insert_resource(0i32);
+        let first = ecs.spawn((Link(None, 1), Pos(1), Stunned(1)));
+        let second = ecs.spawn((Link(Some(first), 2), Pos(2), Stunned(2)));
+        let third = ecs.spawn((Link(Some(second), 3), Pos(3)));
+        ecs.clear_trackers();
+
+        ecs.delete_entity(second);
+        //first's value, and the entities queries still see: first and third, and first's Stunned
+        assert_eq!(*ecs.resource::<i32>().unwrap(), 1201);
+        assert_eq!(ecs.query::<(&Link,)>().filter::<query::Changed<Link>>().iter().count(), 0);
+        let mut left: Vec<i32> = ecs.query::<(&Link, &Pos)>().iter().map(|(_, (link, pos))| link.1 * 10 + pos.0).collect();
+        left.sort();
+        assert_eq!(left, vec![11, 33]);
+        assert_eq!(ecs.get_comp::<Pos>(&third).unwrap().0, 3);
+        assert_eq!(ecs.removed::<Link>().count(), 1);
+    }
+
+    #[derive(Bundle)]
+    struct Stun {
+        pos: Pos,
+        stunned: Stunned,
+    }
+
+    #[derive(Bundle)]
+    struct PosOnly(Pos);
+
+    #[test]
+    fn derived_bundle() {
+        let mut ecs = Ecs::new();
+        let entity = ecs.spawn(Stun { pos: Pos(1), stunned: Stunned(2) });
+        assert_eq!(*ecs.get_comp::<Pos>(&entity).unwrap(), Pos(1));
+        assert_eq!(*ecs.get_comp::<Stunned>(&entity).unwrap(), Stunned(2));
+
+        ecs.entity(&entity).unwrap().insert_bundle(PosOnly(Pos(3)));
+        assert_eq!(*ecs.get_comp::<Pos>(&entity).unwrap(), Pos(3));
+    }
+}
@@ -0,0 +1,387 @@
+use super::archetype::Archetype;
+use super::storage::{Storage, StoragePtr, StorageType};
+use super::ticks::{is_newer, ComponentTicks};
+use super::{Component, ComponentRegister, Ecs, Handle, Ref, RefMut};
+use std::any::TypeId;
+use std::iter::Enumerate;
+use std::marker::PhantomData;
+use std::slice;
+
+//Anything that can be fetched from an entity in a query: &T, &mut T and tuples of those
+pub trait Fetch {
+    type Borrow<'w>;
+    type Item<'q>;
+
+    //Borrows the stores this fetch needs, panics if one of them is already borrowed incompatibly.
+    //Returns None when a component type was never registered, so nothing can match.
+    fn borrow(ecs: &Ecs) -> Option<Self::Borrow<'_>>;
+
+    //False if no entity in the archetype can have the components this fetch needs
+    fn matches(archetype: &Archetype) -> bool;
+
+    //Safety: the archetype must match and a row may only be fetched once per borrow,
+    //items hand out aliasing-free references because every row is a distinct slot in each store
+    unsafe fn fetch<'q>(borrow: &'q Self::Borrow<'_>, archetype: usize, row: usize, entity: &Handle) -> Option<Self::Item<'q>>;
+
+    //The entities of the store every match has to be in, None if the fetch includes a table component.
+    //Queries made only of sparse set and GIVec components walk these instead of every archetype.
+    fn sparse_entities<'a>(borrow: &'a Self::Borrow<'_>) -> Option<&'a [Handle]>;
+}
+
+fn in_archetype<T: Component + 'static>(archetype: &Archetype) -> bool {
+    match T::STORAGE {
+        StorageType::Table => archetype.has(TypeId::of::<T>()),
+        StorageType::SparseSet | StorageType::GIVec => true,
+    }
+}
+
+impl<T: Component + 'static> Fetch for &T {
+    type Borrow<'w> = Ref<'w, Storage<T>>;
+    type Item<'q> = &'q T;
+
+    fn borrow(ecs: &Ecs) -> Option<Self::Borrow<'_>> {
+        ecs.components.get::<ComponentRegister<T>>().map(|store| {
+            store.try_borrow().unwrap_or_else(|_| {
+                panic!("query can't borrow {}, it is already mutably borrowed", std::any::type_name::<T>())
+            })
+        })
+    }
+
+    fn matches(archetype: &Archetype) -> bool {
+        in_archetype::<T>(archetype)
+    }
+
+    unsafe fn fetch<'q>(borrow: &'q Self::Borrow<'_>, archetype: usize, row: usize, entity: &Handle) -> Option<Self::Item<'q>> {
+        match &**borrow {
+            Storage::Table(columns) => columns.get(archetype, row),
+            Storage::SparseSet(set) => set.get(entity),
+            Storage::GIVec(store) => store.get(entity),
+        }
+    }
+
+    fn sparse_entities<'a>(borrow: &'a Self::Borrow<'_>) -> Option<&'a [Handle]> {
+        borrow.entities()
+    }
+}
+
+//Fetching a &mut T marks the component changed at the tick the query was created
+impl<T: Component + 'static> Fetch for &mut T {
+    type Borrow<'w> = (RefMut<'w, Storage<T>>, StoragePtr<T>, &'w ComponentTicks, u32);
+    type Item<'q> = &'q mut T;
+
+    fn borrow(ecs: &Ecs) -> Option<Self::Borrow<'_>> {
+        ecs.components.get::<ComponentRegister<T>>().map(|store| {
+            let mut store = store.try_borrow_mut().unwrap_or_else(|_| {
+                panic!("query can't mutably borrow {}, it is already borrowed", std::any::type_name::<T>())
+            });
+            let ptr = store.as_ptr();
+            (store, ptr, &ecs.ticks[&TypeId::of::<T>()], ecs.change_tick)
+        })
+    }
+
+    fn matches(archetype: &Archetype) -> bool {
+        in_archetype::<T>(archetype)
+    }
+
+    unsafe fn fetch<'q>(borrow: &'q Self::Borrow<'_>, archetype: usize, row: usize, entity: &Handle) -> Option<Self::Item<'q>> {
+        let (_, storage, ticks, tick) = borrow;
+        let comp = storage.get_unchecked_mut(archetype, row, entity)?;
+        ticks.set_changed(entity, *tick);
+        Some(comp)
+    }
+
+    fn sparse_entities<'a>(borrow: &'a Self::Borrow<'_>) -> Option<&'a [Handle]> {
+        //the store stays borrowed for 'a, and queries can't add or remove components
+        unsafe { borrow.1.sparse_entities() }
+    }
+}
+
+macro_rules! impl_fetch_tuple {
+    ($($name:ident),*) => {
+        #[allow(non_snake_case)]
+        impl<$($name: Fetch),*> Fetch for ($($name,)*) {
+            type Borrow<'w> = ($($name::Borrow<'w>,)*);
+            type Item<'q> = ($($name::Item<'q>,)*);
+
+            fn borrow(ecs: &Ecs) -> Option<Self::Borrow<'_>> {
+                Some(($($name::borrow(ecs)?,)*))
+            }
+
+            fn matches(archetype: &Archetype) -> bool {
+                $($name::matches(archetype))&&*
+            }
+
+            unsafe fn fetch<'q>(borrow: &'q Self::Borrow<'_>, archetype: usize, row: usize, entity: &Handle) -> Option<Self::Item<'q>> {
+                let ($($name,)*) = borrow;
+                Some(($($name::fetch($name, archetype, row, entity)?,)*))
+            }
+
+            //the smallest set, every match is in all of them
+            fn sparse_entities<'a>(borrow: &'a Self::Borrow<'_>) -> Option<&'a [Handle]> {
+                let ($($name,)*) = borrow;
+                let mut smallest: Option<&'a [Handle]> = None;
+                $(
+                    let entities = $name::sparse_entities($name)?;
+                    if smallest.map_or(true, |smallest| entities.len() < smallest.len()) {
+                        smallest = Some(entities);
+                    }
+                )*
+                smallest
+            }
+        }
+    };
+}
+
+impl_fetch_tuple!(A);
+impl_fetch_tuple!(A, B);
+impl_fetch_tuple!(A, B, C);
+impl_fetch_tuple!(A, B, C, D);
+impl_fetch_tuple!(A, B, C, D, E);
+impl_fetch_tuple!(A, B, C, D, E, F);
+impl_fetch_tuple!(A, B, C, D, E, F, G);
+impl_fetch_tuple!(A, B, C, D, E, F, G, H);
+
+//Extra conditions on the entities of a query that don't fetch anything
+pub trait QueryFilter {
+    //False if no entity of the archetype can pass
+    fn archetype(&self, archetype: &Archetype) -> bool;
+    //Checked for every entity of the archetypes that passed
+    fn entity(&self, entity: &Handle) -> bool;
+}
+
+//Types that can be passed to Query::filter
+pub trait Filter {
+    fn filter(ecs: &Ecs) -> Box<dyn QueryFilter + '_>;
+}
+
+pub struct With<T>(PhantomData<T>);
+pub struct Without<T>(PhantomData<T>);
+//The component was added since the running system last ran
+pub struct Added<T>(PhantomData<T>);
+//The component was added or mutably accessed since the running system last ran
+pub struct Changed<T>(PhantomData<T>);
+
+//Whether the entity has a T, or doesn't when negated
+struct Has<'w> {
+    ty: TypeId,
+    storage: StorageType,
+    //sparse components are looked up in their ticks, archetypes already know about table components
+    ticks: Option<&'w ComponentTicks>,
+    negated: bool,
+}
+
+impl<'w> Has<'w> {
+    fn new<T: Component + 'static>(ecs: &'w Ecs, negated: bool) -> Has<'w> {
+        Has {
+            ty: TypeId::of::<T>(),
+            storage: T::STORAGE,
+            ticks: ecs.ticks.get(&TypeId::of::<T>()),
+            negated,
+        }
+    }
+}
+
+impl<'w> QueryFilter for Has<'w> {
+    fn archetype(&self, archetype: &Archetype) -> bool {
+        match self.storage {
+            StorageType::Table => archetype.has(self.ty) != self.negated,
+            StorageType::SparseSet | StorageType::GIVec => true,
+        }
+    }
+
+    fn entity(&self, entity: &Handle) -> bool {
+        match self.storage {
+            StorageType::Table => true,
+            StorageType::SparseSet | StorageType::GIVec => self.ticks.is_some_and(|ticks| ticks.contains(entity)) != self.negated,
+        }
+    }
+}
+
+//Whether the entity's T was added, or changed, after the given tick
+struct Since<'w> {
+    ty: TypeId,
+    storage: StorageType,
+    ticks: Option<&'w ComponentTicks>,
+    last_run: u32,
+    this_run: u32,
+    added: bool,
+}
+
+impl<'w> Since<'w> {
+    fn new<T: Component + 'static>(ecs: &'w Ecs, added: bool) -> Since<'w> {
+        Since {
+            ty: TypeId::of::<T>(),
+            storage: T::STORAGE,
+            ticks: ecs.ticks.get(&TypeId::of::<T>()),
+            last_run: ecs.last_change_tick,
+            this_run: ecs.change_tick,
+            added,
+        }
+    }
+}
+
+impl<'w> QueryFilter for Since<'w> {
+    fn archetype(&self, archetype: &Archetype) -> bool {
+        match self.storage {
+            StorageType::Table => archetype.has(self.ty),
+            StorageType::SparseSet | StorageType::GIVec => self.ticks.is_some(),
+        }
+    }
+
+    fn entity(&self, entity: &Handle) -> bool {
+        self.ticks.is_some_and(|ticks| {
+            let tick = if self.added { ticks.added(entity) } else { ticks.changed(entity) };
+            ticks.contains(entity) && is_newer(tick, self.last_run, self.this_run)
+        })
+    }
+}
+
+impl<T: Component + 'static> Filter for With<T> {
+    fn filter(ecs: &Ecs) -> Box<dyn QueryFilter + '_> {
+        Box::new(Has::new::<T>(ecs, false))
+    }
+}
+
+impl<T: Component + 'static> Filter for Without<T> {
+    fn filter(ecs: &Ecs) -> Box<dyn QueryFilter + '_> {
+        Box::new(Has::new::<T>(ecs, true))
+    }
+}
+
+impl<T: Component + 'static> Filter for Added<T> {
+    fn filter(ecs: &Ecs) -> Box<dyn QueryFilter + '_> {
+        Box::new(Since::new::<T>(ecs, true))
+    }
+}
+
+impl<T: Component + 'static> Filter for Changed<T> {
+    fn filter(ecs: &Ecs) -> Box<dyn QueryFilter + '_> {
+        Box::new(Since::new::<T>(ecs, false))
+    }
+}
+
+//Stores stay borrowed for as long as the query lives
+pub struct Query<'w, Q: Fetch> {
+    ecs: &'w Ecs,
+    borrow: Option<Q::Borrow<'w>>,
+    filters: Vec<Box<dyn QueryFilter + 'w>>,
+    marker: PhantomData<Q>,
+}
+
+impl<'w, Q: Fetch> Query<'w, Q> {
+    pub(super) fn new(ecs: &'w Ecs) -> Query<'w, Q> {
+        Query {
+            ecs,
+            borrow: Q::borrow(ecs),
+            filters: Vec::new(),
+            marker: PhantomData,
+        }
+    }
+
+    pub fn filter<F: Filter>(mut self) -> Self {
+        self.filters.push(F::filter(self.ecs));
+        self
+    }
+
+    pub fn with<T: Component + 'static>(self) -> Self {
+        self.filter::<With<T>>()
+    }
+
+    pub fn without<T: Component + 'static>(self) -> Self {
+        self.filter::<Without<T>>()
+    }
+
+    pub fn iter(&mut self) -> QueryIter<'_, 'w, Q> {
+        let borrow = self.borrow.as_ref();
+        QueryIter {
+            ecs: self.ecs,
+            archetypes: self.ecs.archetypes.iter().enumerate(),
+            current: None,
+            sparse: borrow.and_then(|borrow| Q::sparse_entities(borrow)).map(|entities| entities.iter()),
+            borrow,
+            filters: &self.filters,
+        }
+    }
+}
+
+impl<'q, 'w, Q: Fetch> IntoIterator for &'q mut Query<'w, Q> {
+    type Item = (Handle, Q::Item<'q>);
+    type IntoIter = QueryIter<'q, 'w, Q>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct QueryIter<'q, 'w, Q: Fetch> {
+    ecs: &'w Ecs,
+    archetypes: Enumerate<slice::Iter<'q, Archetype>>,
+    //archetype being iterated, its entities and the next row
+    current: Option<(usize, &'q [Handle], usize)>,
+    //entities of the sparse set driving the query instead of the archetypes
+    sparse: Option<slice::Iter<'q, Handle>>,
+    borrow: Option<&'q Q::Borrow<'w>>,
+    filters: &'q [Box<dyn QueryFilter + 'w>],
+}
+
+impl<'q, 'w, Q: Fetch> QueryIter<'q, 'w, Q> {
+    fn matches(&self, archetype: &Archetype) -> bool {
+        Q::matches(archetype) && self.filters.iter().all(|filter| filter.archetype(archetype))
+    }
+}
+
+impl<'q, 'w, Q: Fetch> Iterator for QueryIter<'q, 'w, Q> {
+    type Item = (Handle, Q::Item<'q>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let borrow = self.borrow?;
+        if let Some(sparse) = &mut self.sparse {
+            for handle in sparse {
+                let entity = match self.ecs.entities.get(handle) {
+                    Some(entity) => entity,
+                    None => continue,
+                };
+                let archetype = self.ecs.archetypes.get(entity.archetype);
+                //an entity that is being deleted was already taken out of its table, see Ecs::delete_entity
+                if archetype.entities.get(entity.row) != Some(handle) {
+                    continue;
+                }
+                let matches = Q::matches(archetype) && self.filters.iter().all(|filter| filter.archetype(archetype) && filter.entity(handle));
+                if !matches {
+                    continue;
+                }
+
+                //every entity is in the set once, so no two items alias
+                if let Some(item) = unsafe { Q::fetch(borrow, entity.archetype, entity.row, handle) } {
+                    return Some((*handle, item));
+                }
+            }
+            return None;
+        }
+
+        loop {
+            if let Some((archetype, entities, row)) = &mut self.current {
+                while let Some(handle) = entities.get(*row) {
+                    let current = *row;
+                    *row += 1;
+
+                    if !self.filters.iter().all(|filter| filter.entity(handle)) {
+                        continue;
+                    }
+
+                    //every row is visited once, so no two items alias
+                    if let Some(item) = unsafe { Q::fetch(borrow, *archetype, current, handle) } {
+                        return Some((*handle, item));
+                    }
+                }
+            }
+
+            let (index, archetype) = self.archetypes.next()?;
+            self.current = if self.matches(archetype) {
+                Some((index, &archetype.entities, 0))
+            } else {
+                None
+            };
+        }
+    }
+}
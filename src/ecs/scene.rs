@@ -0,0 +1,349 @@
+use super::hierarchy::{Children, Parent, Transform};
+use super::snapshot::{restore_comp, snapshot_comp, Restored};
+use super::{Component, Ecs, Handle};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+//Entities of a world written out with the components the SceneRegistry knows about.
+//Handles are the ones the entities had when they were saved, they are replaced by fresh ones on load.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Scene {
+    pub entities: Vec<SceneEntity>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneEntity {
+    pub entity: Handle,
+    //registered name -> component
+    pub components: BTreeMap<String, Value>,
+}
+
+impl Scene {
+    pub fn to_json(&self) -> Result<String, SceneError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Scene, SceneError> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+#[derive(Fail, Debug)]
+pub enum SceneError {
+    #[fail(display = "component {} isn't registered", _0)]
+    UnknownComponent(String),
+    #[fail(display = "snapshot is malformed: {}", _0)]
+    BadSnapshot(String),
+    #[fail(display = "entity {:?} can't be attached to {:?}, it is one of its ancestors", _0, _1)]
+    CyclicHierarchy(Handle, Handle),
+    #[fail(display = "{}", _0)]
+    Json(#[cause] serde_json::Error),
+    #[fail(display = "{}", _0)]
+    Binary(#[cause] bincode::Error),
+}
+
+impl From<serde_json::Error> for SceneError {
+    fn from(error: serde_json::Error) -> SceneError {
+        SceneError::Json(error)
+    }
+}
+
+impl From<bincode::Error> for SceneError {
+    fn from(error: bincode::Error) -> SceneError {
+        SceneError::Binary(error)
+    }
+}
+
+//Saved handle -> handle of the entity that was loaded in its place
+pub struct EntityMap(HashMap<Handle, Handle>);
+
+impl EntityMap {
+    //None if the entity wasn't part of the scene
+    pub fn get(&self, saved: &Handle) -> Option<Handle> {
+        self.0.get(saved).cloned()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Handle, &Handle)> {
+        self.0.iter()
+    }
+}
+
+//Components that hold entity handles, they are pointed at the loaded entities before being inserted
+pub trait MapEntities {
+    fn map_entities(&mut self, map: &EntityMap);
+}
+
+//Handles to entities outside of the scene are kept, so a scene can be loaded into the world it was saved from
+impl MapEntities for Parent {
+    fn map_entities(&mut self, map: &EntityMap) {
+        self.0 = map.get(&self.0).unwrap_or(self.0);
+    }
+}
+
+//Children outside of the scene belong to the entity the scene was saved from, not the loaded one
+impl MapEntities for Children {
+    fn map_entities(&mut self, map: &EntityMap) {
+        self.0 = self.0.iter().filter_map(|child| map.get(child)).collect();
+    }
+}
+
+pub(super) struct Registration {
+    pub(super) name: String,
+    save: fn(&Ecs, &Handle) -> Option<serde_json::Result<Value>>,
+    load: fn(&mut Ecs, &Handle, Value, &EntityMap) -> Result<(), SceneError>,
+    pub(super) snapshot: fn(&Ecs) -> bincode::Result<Vec<u8>>,
+    pub(super) restore: fn(&[u8]) -> bincode::Result<Restored>,
+}
+
+fn save_comp<T: Component + Serialize + 'static>(ecs: &Ecs, handle: &Handle) -> Option<serde_json::Result<Value>> {
+    ecs.get_comp::<T>(handle).map(|comp| serde_json::to_value(&*comp))
+}
+
+fn load_comp<T: Component + DeserializeOwned + 'static>(ecs: &mut Ecs, handle: &Handle, value: Value, _map: &EntityMap) -> Result<(), SceneError> {
+    let comp: T = serde_json::from_value(value)?;
+    ecs.add_comp(handle, comp).expect("entity was created by the scene");
+    Ok(())
+}
+
+fn load_mapped<T: Component + DeserializeOwned + MapEntities + 'static>(ecs: &mut Ecs, handle: &Handle, value: Value, map: &EntityMap) -> Result<(), SceneError> {
+    let mut comp: T = serde_json::from_value(value)?;
+    comp.map_entities(map);
+    ecs.add_comp(handle, comp).expect("entity was created by the scene");
+    Ok(())
+}
+
+//The hierarchy is rebuilt with set_parent so a scene can't load a cycle.
+//A parent that doesn't exist, e.g. outside of a scene loaded into another world, leaves the entity a root.
+fn load_parent(ecs: &mut Ecs, handle: &Handle, value: Value, map: &EntityMap) -> Result<(), SceneError> {
+    let mut parent: Parent = serde_json::from_value(value)?;
+    parent.map_entities(map);
+    if ecs.entity_exists(&parent.0) && !ecs.set_parent(handle, &parent.0) {
+        return Err(SceneError::CyclicHierarchy(*handle, parent.0));
+    }
+    Ok(())
+}
+
+//Children are rebuilt from the Parents, the saved ones only give their order, see SceneRegistry::load
+fn load_children(_ecs: &mut Ecs, _handle: &Handle, value: Value, _map: &EntityMap) -> Result<(), SceneError> {
+    serde_json::from_value::<Children>(value)?;
+    Ok(())
+}
+
+//The component types that are written to scenes and snapshots, components of other types are left out.
+//Names are what the components are saved under, so they have to stay the same for old scenes to load.
+pub struct SceneRegistry {
+    pub(super) types: Vec<Registration>,
+}
+
+impl SceneRegistry {
+    //Knows the hierarchy components, GlobalTransform is left out as TransformPropagation recomputes it
+    pub fn new() -> SceneRegistry {
+        let mut registry = SceneRegistry { types: Vec::new() };
+        registry.add(Registration {
+            name: "Parent".to_string(),
+            save: save_comp::<Parent>,
+            load: load_parent,
+            snapshot: snapshot_comp::<Parent>,
+            restore: restore_comp::<Parent>,
+        });
+        registry.add(Registration {
+            name: "Children".to_string(),
+            save: save_comp::<Children>,
+            load: load_children,
+            snapshot: snapshot_comp::<Children>,
+            restore: restore_comp::<Children>,
+        });
+        registry.register::<Transform>("Transform");
+        registry
+    }
+
+    fn add(&mut self, registration: Registration) -> &mut Self {
+        assert!(
+            self.types.iter().all(|other| other.name != registration.name),
+            "component name {} is registered twice",
+            registration.name
+        );
+        self.types.push(registration);
+        self
+    }
+
+    pub fn register<T: Component + Serialize + DeserializeOwned + 'static>(&mut self, name: &str) -> &mut Self {
+        self.add(Registration {
+            name: name.to_string(),
+            save: save_comp::<T>,
+            load: load_comp::<T>,
+            snapshot: snapshot_comp::<T>,
+            restore: restore_comp::<T>,
+        })
+    }
+
+    //For components that hold entity handles
+    pub fn register_mapped<T: Component + Serialize + DeserializeOwned + MapEntities + 'static>(&mut self, name: &str) -> &mut Self {
+        self.add(Registration {
+            name: name.to_string(),
+            save: save_comp::<T>,
+            load: load_mapped::<T>,
+            snapshot: snapshot_comp::<T>,
+            restore: restore_comp::<T>,
+        })
+    }
+
+    pub(super) fn registration(&self, name: &str) -> Result<&Registration, SceneError> {
+        self.types
+            .iter()
+            .find(|registration| registration.name == name)
+            .ok_or_else(|| SceneError::UnknownComponent(name.to_string()))
+    }
+
+    //Entities that don't exist are skipped
+    pub fn save(&self, ecs: &Ecs, entities: &[Handle]) -> Result<Scene, SceneError> {
+        let mut scene = Scene::default();
+        for handle in entities.iter().filter(|handle| ecs.entity_exists(handle)) {
+            let mut components = BTreeMap::new();
+            for registration in &self.types {
+                if let Some(value) = (registration.save)(ecs, handle) {
+                    components.insert(registration.name.clone(), value?);
+                }
+            }
+            scene.entities.push(SceneEntity {
+                entity: *handle,
+                components,
+            });
+        }
+        Ok(scene)
+    }
+
+    pub fn save_world(&self, ecs: &Ecs) -> Result<Scene, SceneError> {
+        let entities: Vec<Handle> = ecs.iter_entities().collect();
+        self.save(ecs, &entities)
+    }
+
+    //Spawns a new entity for every entity of the scene and returns which saved handle became which.
+    //Parents are attached with Ecs::set_parent, a scene whose Parents form a cycle fails to load.
+    //On error the entities loaded so far stay in the world.
+    pub fn load(&self, ecs: &mut Ecs, scene: &Scene) -> Result<EntityMap, SceneError> {
+        let map = EntityMap(scene.entities.iter().map(|saved| (saved.entity, ecs.create_entity())).collect());
+
+        for saved in &scene.entities {
+            let handle = map.get(&saved.entity).expect("every saved entity was mapped");
+            for (name, value) in &saved.components {
+                (self.registration(name)?.load)(ecs, &handle, value.clone(), &map)?;
+            }
+        }
+
+        //set_parent appends children in the order they were loaded, the saved order is kept
+        //as long as it lists the same children
+        for saved in &scene.entities {
+            if let Some(value) = saved.components.get("Children") {
+                let mut order: Children = serde_json::from_value(value.clone())?;
+                order.map_entities(&map);
+                let handle = map.get(&saved.entity).expect("every saved entity was mapped");
+                if let Some(children) = ecs.get_comp_mut::<Children>(&handle) {
+                    let rebuilt: HashSet<&Handle> = children.0.iter().collect();
+                    if order.0.len() == children.0.len() && order.0.iter().all(|child| rebuilt.contains(child)) {
+                        children.0 = order.0;
+                    }
+                }
+            }
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::hierarchy::{GlobalTransform, TransformPropagation};
+    use crate::ecs::system::{Schedule, Stage};
+
+    fn entity(handle: Handle, components: Vec<(&str, Value)>) -> SceneEntity {
+        SceneEntity {
+            entity: handle,
+            components: components.into_iter().map(|(name, value)| (name.to_string(), value)).collect(),
+        }
+    }
+
+    fn children(handles: &[Handle]) -> Value {
+        serde_json::to_value(Children(handles.to_vec())).unwrap()
+    }
+
+    fn parent(handle: Handle) -> Value {
+        serde_json::to_value(Parent(handle)).unwrap()
+    }
+
+    #[test]
+    fn hierarchy_is_rebuilt_on_load() {
+        let registry = SceneRegistry::new();
+        let mut ecs = Ecs::new();
+        let outside = ecs.create_entity();
+        let root = ecs.spawn((Transform::from_translation([1.0, 0.0, 0.0]),));
+        let (first, second) = (ecs.create_entity(), ecs.create_entity());
+        ecs.set_parent(&second, &root);
+        ecs.set_parent(&first, &root);
+        ecs.set_parent(&root, &outside);
+
+        let scene = registry.save(&ecs, &[second, first, root]).unwrap();
+        let scene = Scene::from_json(&scene.to_json().unwrap()).unwrap();
+        let map = registry.load(&mut ecs, &scene).unwrap();
+        let loaded = |handle: &Handle| map.get(handle).unwrap();
+
+        //the order of the saved Children is kept, not the order the entities were loaded in
+        assert_eq!(ecs.get_comp::<Children>(&loaded(&root)).unwrap().0, vec![loaded(&second), loaded(&first)]);
+        assert_eq!(ecs.get_comp::<Parent>(&loaded(&first)).unwrap().0, loaded(&root));
+        //a parent outside of the scene lists the loaded entity next to the one it was saved from
+        assert_eq!(ecs.get_comp::<Parent>(&loaded(&root)).unwrap().0, outside);
+        assert_eq!(ecs.get_comp::<Children>(&outside).unwrap().0, vec![root, loaded(&root)]);
+    }
+
+    #[test]
+    fn bad_hierarchy() {
+        let registry = SceneRegistry::new();
+        let mut scratch = Ecs::new();
+        let (a, b, c) = (scratch.create_entity(), scratch.create_entity(), scratch.create_entity());
+
+        //Children that point at each other without Parents are dropped, so propagation doesn't loop forever
+        let scene = Scene {
+            entities: vec![
+                entity(a, vec![("Children", children(&[b])), ("Transform", serde_json::to_value(Transform::default()).unwrap())]),
+                entity(b, vec![("Children", children(&[a])), ("Transform", serde_json::to_value(Transform::default()).unwrap())]),
+            ],
+        };
+        let mut ecs = Ecs::new();
+        let map = registry.load(&mut ecs, &scene).unwrap();
+        assert!(ecs.get_comp::<Children>(&map.get(&a).unwrap()).is_none());
+        assert!(ecs.get_comp::<Children>(&map.get(&b).unwrap()).is_none());
+        let mut schedule = Schedule::builder().add_system_to_stage(Stage::PostUpdate, TransformPropagation).build().unwrap();
+        schedule.run(&mut ecs);
+        assert_eq!(ecs.query::<(&GlobalTransform,)>().iter().count(), 2);
+
+        //Children that disagree with the Parents are rebuilt from the Parents
+        let scene = Scene {
+            entities: vec![
+                entity(a, vec![("Children", children(&[b, c]))]),
+                entity(b, vec![("Parent", parent(a))]),
+                entity(c, vec![("Children", children(&[a]))]),
+            ],
+        };
+        let mut ecs = Ecs::new();
+        let map = registry.load(&mut ecs, &scene).unwrap();
+        assert_eq!(ecs.get_comp::<Children>(&map.get(&a).unwrap()).unwrap().0, vec![map.get(&b).unwrap()]);
+        assert!(ecs.get_comp::<Children>(&map.get(&c).unwrap()).is_none());
+        assert!(ecs.get_comp::<Parent>(&map.get(&c).unwrap()).is_none());
+
+        //Parents that form a cycle don't load
+        let scene = Scene {
+            entities: vec![
+                entity(a, vec![("Parent", parent(c))]),
+                entity(b, vec![("Parent", parent(a))]),
+                entity(c, vec![("Parent", parent(b))]),
+            ],
+        };
+        let mut ecs = Ecs::new();
+        match registry.load(&mut ecs, &scene) {
+            Err(SceneError::CyclicHierarchy(_, _)) => {}
+            other => panic!("loaded a cyclic hierarchy: {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use super::archetype::ComponentInfo;
+use super::entity::Entity;
+use super::generational_index::index::Allocator;
+use super::scene::{SceneError, SceneRegistry};
+use super::storage::StorageType;
+use super::ticks::ComponentTicks;
+use super::{Component, Ecs, Handle};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::any::TypeId;
+use std::collections::{HashMap, HashSet};
+
+//The components of one registered type, decoded but not yet in the world
+pub(super) struct Restored {
+    ty: TypeId,
+    storage: StorageType,
+    entities: Vec<Handle>,
+    //writes them into their store once every entity has its archetype and row
+    put: Box<dyn FnOnce(&mut Ecs)>,
+}
+
+//Entity allocator, entities with their rows, and the bytes of every registered store by name
+type Contents = (Allocator, Vec<(Handle, usize)>, Vec<(String, Vec<u8>)>);
+
+//Binary copy of the entities of a world and their registered components, taken with SceneRegistry::snapshot.
+//Unlike a Scene it keeps the handles as they are, meant for rollback and replays rather than for files people edit.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    bytes: Vec<u8>,
+}
+
+impl Snapshot {
+    pub fn from_bytes(bytes: Vec<u8>) -> Snapshot {
+        Snapshot { bytes }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+pub(super) fn snapshot_comp<T: Component + Serialize + 'static>(ecs: &Ecs) -> bincode::Result<Vec<u8>> {
+    let mut query = ecs.query::<(&T,)>();
+    let comps: Vec<(Handle, &T)> = query.iter().map(|(handle, (comp,))| (handle, comp)).collect();
+    bincode::serialize(&comps)
+}
+
+pub(super) fn restore_comp<T: Component + DeserializeOwned + 'static>(bytes: &[u8]) -> bincode::Result<Restored> {
+    let comps: Vec<(Handle, T)> = bincode::deserialize(bytes)?;
+    Ok(Restored {
+        ty: TypeId::of::<T>(),
+        storage: T::STORAGE,
+        entities: comps.iter().map(|(handle, _)| *handle).collect(),
+        put: Box::new(move |ecs| put_restored(ecs, comps)),
+    })
+}
+
+//Fills the store of T directly, without hooks. The entities already sit in archetypes with a column for T.
+fn put_restored<T: Component + 'static>(ecs: &mut Ecs, comps: Vec<(Handle, T)>) {
+    ecs.register_type::<T>();
+    let change_tick = ecs.change_tick;
+    let ticks = ecs.ticks.get_mut(&TypeId::of::<T>()).expect("component type was just registered");
+    for (handle, _) in &comps {
+        ticks.insert(handle, change_tick);
+    }
+
+    if T::STORAGE != StorageType::Table {
+        let storage = ecs.storage_mut::<T>();
+        for (handle, comp) in comps {
+            storage.insert_by_entity(&handle, comp);
+        }
+        return;
+    }
+
+    //columns are filled from the first row on, so they are pushed in row order
+    let mut rows: Vec<(Entity, T)> = comps
+        .into_iter()
+        .map(|(handle, comp)| (*ecs.entities.get(&handle).expect("entity was restored from the snapshot"), comp))
+        .collect();
+    rows.sort_by_key(|(entity, _)| (entity.archetype, entity.row));
+    let columns = ecs.columns_mut::<T>();
+    for (entity, comp) in rows {
+        let column = columns.column_mut(entity.archetype);
+        debug_assert_eq!(column.len(), entity.row);
+        column.push(comp);
+    }
+}
+
+impl SceneRegistry {
+    //The entity allocator goes in whole, so restoring gives back the same handles and hands out the same ones next.
+    //Resources and change ticks aren't part of it.
+    pub fn snapshot(&self, ecs: &Ecs) -> Result<Snapshot, SceneError> {
+        //with their rows so restored archetypes iterate in the same order
+        let entities: Vec<(Handle, usize)> = ecs.entities.iter().map(|(handle, entity)| (handle, entity.row)).collect();
+        let mut stores = Vec::with_capacity(self.types.len());
+        for registration in &self.types {
+            stores.push((registration.name.as_str(), (registration.snapshot)(ecs)?));
+        }
+        Ok(Snapshot {
+            bytes: bincode::serialize(&(ecs.entities.allocator(), entities, stores))?,
+        })
+    }
+
+    //Replaces every entity of the world with the ones of the snapshot, restored components count as added.
+    //The stores are refilled directly: no hooks run on registered types, nothing shows up as removed and Parent
+    //and Children come back as they were saved. Components of types that aren't registered are dropped with the
+    //old entities after their on_remove hooks ran, ones derived from others like GlobalTransform are put back by
+    //the systems that compute them.
+    //Queries iterate in the same order as when the snapshot was taken if it is restored into the same world,
+    //unless entities had table components that aren't registered.
+    //The snapshot is decoded and checked before the world is touched, so on error the world is left as it was.
+    pub fn restore(&self, ecs: &mut Ecs, snapshot: &Snapshot) -> Result<(), SceneError> {
+        let (mut allocator, mut entities, stores): Contents = bincode::deserialize(&snapshot.bytes)?;
+        let mut restored = Vec::with_capacity(stores.len());
+        for (name, bytes) in &stores {
+            restored.push((self.registration(name)?.restore)(bytes)?);
+        }
+
+        //the table types of every entity, which decide its archetype
+        allocator.flush();
+        let mut types: HashMap<Handle, Vec<TypeId>> = HashMap::with_capacity(entities.len());
+        for (handle, _) in &entities {
+            if !allocator.is_live(&handle.raw()) || types.insert(*handle, Vec::new()).is_some() {
+                return Err(SceneError::BadSnapshot(format!("entity {:?} is dead or listed twice", handle)));
+            }
+        }
+        for store in &restored {
+            let mut seen = HashSet::with_capacity(store.entities.len());
+            for handle in &store.entities {
+                let entity_types = match types.get_mut(handle) {
+                    Some(entity_types) if seen.insert(*handle) => entity_types,
+                    _ => return Err(SceneError::BadSnapshot(format!("components of entity {:?} don't match its entry", handle))),
+                };
+                if store.storage == StorageType::Table {
+                    entity_types.push(store.ty);
+                }
+            }
+        }
+
+        //components of types that aren't registered don't come back, their hooks run like on delete_entity so
+        //the ones that own something outside of the Ecs let it go. The registered ones are dropped without hooks.
+        let registered: HashSet<TypeId> = restored.iter().map(|store| store.ty).collect();
+        let unregistered: Vec<(TypeId, ComponentInfo)> = ecs.component_info.iter()
+            .filter(|(ty, _)| !registered.contains(ty))
+            .map(|(ty, info)| (*ty, *info))
+            .collect();
+        let old_entities: Vec<Handle> = ecs.iter_entities().collect();
+        for (ty, info) in &unregistered {
+            match info.storage {
+                StorageType::Table => {
+                    let tables: Vec<(usize, Vec<Handle>)> = ecs.archetypes.iter().enumerate()
+                        .filter(|(_, archetype)| archetype.has(*ty))
+                        .map(|(archetype, table)| (archetype, table.entities.clone()))
+                        .collect();
+                    for (archetype, table) in tables {
+                        for handle in table.iter().rev() {
+                            (info.take_last)(ecs, archetype, handle);
+                        }
+                    }
+                }
+                StorageType::SparseSet | StorageType::GIVec => {
+                    for handle in &old_entities {
+                        (info.take_sparse)(ecs, handle);
+                    }
+                }
+            }
+        }
+
+        for (ty, info) in &ecs.component_info {
+            (info.clear)(&mut ecs.components);
+            ecs.ticks.insert(*ty, ComponentTicks::new());
+        }
+        ecs.archetypes.clear();
+        ecs.entities.restore_allocator(allocator);
+        //rows are handed out in the order the entities had them, so every table comes back in the same order
+        entities.sort_by_key(|&(_, row)| row);
+        for (handle, _) in &entities {
+            let mut entity_types = types.remove(handle).expect("entity was checked above");
+            entity_types.sort();
+            let archetype = ecs.archetypes.find_or_create(entity_types);
+            let table = ecs.archetypes.get_mut(archetype);
+            ecs.entities.insert_reserved(handle, Entity { archetype, row: table.len() });
+            table.entities.push(*handle);
+        }
+        for store in restored {
+            (store.put)(ecs);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::hierarchy::Parent;
+    use serde::Deserialize;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static HOOKS_RUN: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Health(i32);
+    impl Component for Health {
+        fn on_add(&mut self, _ecs: &Ecs, _entity: &Handle) {
+            HOOKS_RUN.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn on_remove(&mut self, _ecs: &Ecs, _entity: &Handle) {
+            HOOKS_RUN.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Burning(u32);
+    impl Component for Burning {
+        const STORAGE: StorageType = StorageType::SparseSet;
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Frozen(u32);
+    impl Component for Frozen {
+        const STORAGE: StorageType = StorageType::GIVec;
+    }
+
+    static UNREGISTERED_REMOVED: AtomicUsize = AtomicUsize::new(0);
+
+    //Stands for a component owning something outside of the Ecs that doesn't belong in snapshots
+    struct Unregistered;
+    impl Component for Unregistered {
+        fn on_remove(&mut self, ecs: &Ecs, entity: &Handle) {
+            assert!(ecs.entity_exists(entity));
+            UNREGISTERED_REMOVED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    struct UnregisteredSparse;
+    impl Component for UnregisteredSparse {
+        const STORAGE: StorageType = StorageType::SparseSet;
+
+        fn on_remove(&mut self, _ecs: &Ecs, _entity: &Handle) {
+            UNREGISTERED_REMOVED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn healths(ecs: &Ecs) -> Vec<(Handle, i32)> {
+        let mut healths: Vec<(Handle, i32)> = ecs.query::<(&Health,)>().iter().map(|(handle, (health,))| (handle, health.0)).collect();
+        healths.sort_by_key(|(handle, _)| handle.index());
+        healths
+    }
+
+    #[test]
+    fn restore_fills_stores_directly() {
+        let mut registry = SceneRegistry::new();
+        registry.register::<Health>("Health").register::<Burning>("Burning").register::<Frozen>("Frozen");
+        let mut ecs = Ecs::new();
+        let entities: Vec<Handle> = (0..6).map(|i| ecs.spawn((Health(i),))).collect();
+        ecs.add_comp(&entities[1], Burning(3)).unwrap();
+        ecs.add_comp(&entities[3], Frozen(2)).unwrap();
+        ecs.add_comp(&entities[2], Unregistered).unwrap();
+        ecs.set_parent(&entities[4], &entities[0]);
+        ecs.delete_entity(entities[5]);
+        let saved = healths(&ecs);
+        let snapshot = registry.snapshot(&ecs).unwrap();
+
+        ecs.delete_entity(entities[0]);
+        ecs.get_comp_mut::<Health>(&entities[3]).unwrap().0 = 30;
+        ecs.remove_comp::<Burning>(&entities[1]);
+        ecs.remove_comp::<Frozen>(&entities[3]);
+        ecs.add_comp(&entities[4], Frozen(5)).unwrap();
+        let spawned = ecs.spawn((Health(7),));
+        ecs.add_comp(&spawned, Unregistered).unwrap();
+        ecs.add_comp(&entities[3], UnregisteredSparse).unwrap();
+        ecs.clear_trackers();
+
+        let hooks_run = HOOKS_RUN.load(Ordering::SeqCst);
+        registry.restore(&mut ecs, &snapshot).unwrap();
+        assert_eq!(HOOKS_RUN.load(Ordering::SeqCst), hooks_run);
+        //the components that are dropped for good get to let go of what they own
+        assert_eq!(UNREGISTERED_REMOVED.load(Ordering::SeqCst), 3);
+        assert_eq!(ecs.removed::<Health>().count(), 0);
+
+        assert_eq!(healths(&ecs), saved);
+        assert!(!ecs.entity_exists(&spawned));
+        assert_eq!(*ecs.get_comp::<Burning>(&entities[1]).unwrap(), Burning(3));
+        assert_eq!(*ecs.get_comp::<Frozen>(&entities[3]).unwrap(), Frozen(2));
+        assert!(ecs.get_comp::<Frozen>(&entities[4]).is_none());
+        assert_eq!(*ecs.get_comp::<Parent>(&entities[4]).unwrap(), Parent(entities[0]));
+        assert!(ecs.get_comp::<Unregistered>(&entities[2]).is_none());
+        assert!(ecs.is_added::<Health>(&entities[3]));
+
+        //the restored world can change shape like any other
+        ecs.remove_comp::<Health>(&entities[0]);
+        ecs.add_comp(&entities[2], Burning(1)).unwrap();
+        assert_eq!(ecs.query::<(&Health,)>().iter().count(), 4);
+        assert_eq!(ecs.query::<(&Burning,)>().iter().count(), 2);
+    }
+}
@@ -0,0 +1,82 @@
+use super::Handle;
+
+//Components packed densely in insertion order with an entity index lookup on the side.
+//Adding and removing are O(1) and never move the entity between archetypes.
+pub struct SparseSet<T> {
+    sparse: Vec<Option<usize>>,
+    dense: Vec<T>,
+    entities: Vec<Handle>,
+}
+
+impl<T> SparseSet<T> {
+    pub fn new() -> SparseSet<T> {
+        SparseSet {
+            sparse: Vec::new(),
+            dense: Vec::new(),
+            entities: Vec::new(),
+        }
+    }
+
+    fn dense_index(&self, entity: &Handle) -> Option<usize> {
+        self.sparse.get(entity.index()).cloned().and_then(|dense| dense)
+    }
+
+    pub fn contains(&self, entity: &Handle) -> bool {
+        self.dense_index(entity).is_some()
+    }
+
+    //Replaces and returns the entity's previous value
+    pub fn insert(&mut self, entity: &Handle, value: T) -> Option<T> {
+        if let Some(dense) = self.dense_index(entity) {
+            return Some(std::mem::replace(&mut self.dense[dense], value));
+        }
+
+        if self.sparse.len() <= entity.index() {
+            self.sparse.resize(entity.index() + 1, None);
+        }
+        self.sparse[entity.index()] = Some(self.dense.len());
+        self.dense.push(value);
+        self.entities.push(*entity);
+        None
+    }
+
+    pub fn remove(&mut self, entity: &Handle) -> Option<T> {
+        let dense = self.dense_index(entity)?;
+        self.sparse[entity.index()] = None;
+        self.entities.swap_remove(dense);
+        if let Some(moved) = self.entities.get(dense) {
+            self.sparse[moved.index()] = Some(dense);
+        }
+        Some(self.dense.swap_remove(dense))
+    }
+
+    pub fn get(&self, entity: &Handle) -> Option<&T> {
+        self.dense_index(entity).map(|dense| &self.dense[dense])
+    }
+
+    pub fn get_mut(&mut self, entity: &Handle) -> Option<&mut T> {
+        self.dense_index(entity).map(move |dense| &mut self.dense[dense])
+    }
+
+    //Safety: no other reference to the entity's value may be alive
+    pub(super) unsafe fn get_unchecked_mut<'a>(set: *mut SparseSet<T>, entity: &Handle) -> Option<&'a mut T> {
+        (*set).dense_index(entity).map(|dense| &mut *(*set).dense.as_mut_ptr().add(dense))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Handle, &T)> {
+        self.entities.iter().zip(self.dense.iter())
+    }
+
+    //In the same order as iter
+    pub fn entities(&self) -> &[Handle] {
+        &self.entities
+    }
+
+    pub fn len(&self) -> usize {
+        self.dense.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dense.is_empty()
+    }
+}
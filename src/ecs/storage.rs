@@ -0,0 +1,127 @@
+use super::archetype::Columns;
+use super::entity::Entity;
+use super::givec_store::GIVecStore;
+use super::sparse_set::SparseSet;
+use super::Handle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageType {
+    //Columns of archetype tables, fastest to iterate
+    Table,
+    //One sparse set per type, for components that are added and removed all the time
+    SparseSet,
+    //One GIVec per type like before archetype tables, to benchmark the tables against. See GIVecStore.
+    GIVec,
+}
+
+//Where the components of one type live, picked by Component::STORAGE
+pub enum Storage<T> {
+    Table(Columns<T>),
+    SparseSet(SparseSet<T>),
+    GIVec(GIVecStore<T>),
+}
+
+impl<T> Storage<T> {
+    pub(super) fn new(storage: StorageType) -> Storage<T> {
+        match storage {
+            StorageType::Table => Storage::Table(Columns::new()),
+            StorageType::SparseSet => Storage::SparseSet(SparseSet::new()),
+            StorageType::GIVec => Storage::GIVec(GIVecStore::new()),
+        }
+    }
+
+    pub(super) fn get(&self, entity: &Entity, handle: &Handle) -> Option<&T> {
+        match self {
+            Storage::Table(columns) => columns.get(entity.archetype, entity.row),
+            Storage::SparseSet(set) => set.get(handle),
+            Storage::GIVec(store) => store.get(handle),
+        }
+    }
+
+    pub(super) fn get_mut(&mut self, entity: &Entity, handle: &Handle) -> Option<&mut T> {
+        match self {
+            Storage::Table(columns) => columns.get_mut(entity.archetype, entity.row),
+            Storage::SparseSet(set) => set.get_mut(handle),
+            Storage::GIVec(store) => store.get_mut(handle),
+        }
+    }
+
+    //Taken once when a query borrows the store, so fetching rows doesn't reborrow the whole store
+    //while items from earlier rows are still alive
+    pub(super) fn as_ptr(&mut self) -> StoragePtr<T> {
+        match self {
+            Storage::Table(columns) => StoragePtr::Table(columns),
+            Storage::SparseSet(set) => StoragePtr::SparseSet(set),
+            Storage::GIVec(store) => StoragePtr::GIVec(store),
+        }
+    }
+
+    pub(super) fn columns_mut(&mut self) -> &mut Columns<T> {
+        match self {
+            Storage::Table(columns) => columns,
+            _ => panic!("component is stored by entity, not in tables"),
+        }
+    }
+
+    //The stores other than tables are looked up by entity, the entity doesn't move between archetypes.
+    //Replaces and returns the entity's previous component.
+    pub(super) fn insert_by_entity(&mut self, handle: &Handle, comp: T) -> Option<T> {
+        match self {
+            Storage::Table(_) => panic!("component is stored in tables, not by entity"),
+            Storage::SparseSet(set) => set.insert(handle, comp),
+            Storage::GIVec(store) => store.insert(handle, comp),
+        }
+    }
+
+    pub(super) fn remove_by_entity(&mut self, handle: &Handle) -> Option<T> {
+        match self {
+            Storage::Table(_) => panic!("component is stored in tables, not by entity"),
+            Storage::SparseSet(set) => set.remove(handle),
+            Storage::GIVec(store) => store.remove(handle),
+        }
+    }
+
+    //None for tables
+    pub(super) fn entities(&self) -> Option<&[Handle]> {
+        match self {
+            Storage::Table(_) => None,
+            Storage::SparseSet(set) => Some(set.entities()),
+            Storage::GIVec(store) => Some(store.entities()),
+        }
+    }
+}
+
+//The variant of a Storage with a raw pointer to its contents
+pub enum StoragePtr<T> {
+    Table(*mut Columns<T>),
+    SparseSet(*mut SparseSet<T>),
+    GIVec(*mut GIVecStore<T>),
+}
+
+impl<T> Clone for StoragePtr<T> {
+    fn clone(&self) -> StoragePtr<T> {
+        *self
+    }
+}
+
+impl<T> Copy for StoragePtr<T> {}
+
+impl<T> StoragePtr<T> {
+    //Safety: no other reference to the entity's component may be alive
+    pub(super) unsafe fn get_unchecked_mut<'a>(self, archetype: usize, row: usize, handle: &Handle) -> Option<&'a mut T> {
+        match self {
+            StoragePtr::Table(columns) => Some(Columns::get_unchecked_mut(columns, archetype, row)),
+            StoragePtr::SparseSet(set) => SparseSet::get_unchecked_mut(set, handle),
+            StoragePtr::GIVec(store) => GIVecStore::get_unchecked_mut(store, handle),
+        }
+    }
+
+    //Safety: the store must outlive 'a and no entity may be added to or removed from it meanwhile
+    pub(super) unsafe fn sparse_entities<'a>(self) -> Option<&'a [Handle]> where T: 'a {
+        match self {
+            StoragePtr::Table(_) => None,
+            StoragePtr::SparseSet(set) => Some((*set).entities()),
+            StoragePtr::GIVec(store) => Some((*store).entities()),
+        }
+    }
+}
@@ -0,0 +1,408 @@
+use super::{Component, Ecs, Resource};
+use rayon::prelude::*;
+use std::any::{type_name, TypeId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Stage {
+    Startup,
+    PreUpdate,
+    Update,
+    PostUpdate,
+    Render,
+}
+
+impl Stage {
+    //Stages that run every frame, in order. Startup only runs on the first frame.
+    pub const FRAME: [Stage; 4] = [Stage::PreUpdate, Stage::Update, Stage::PostUpdate, Stage::Render];
+    //Stages of one simulation tick when the game loop runs them at a fixed rate, apart from Render
+    pub const FIXED: [Stage; 3] = [Stage::PreUpdate, Stage::Update, Stage::PostUpdate];
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AccessType {
+    id: TypeId,
+    name: &'static str,
+}
+
+impl AccessType {
+    fn of<T: 'static>() -> AccessType {
+        AccessType {
+            id: TypeId::of::<T>(),
+            name: type_name::<T>(),
+        }
+    }
+}
+
+//The component and resource types a system reads and writes
+#[derive(Debug, Clone, Default)]
+pub struct Access {
+    reads: Vec<AccessType>,
+    writes: Vec<AccessType>,
+}
+
+impl Access {
+    pub fn new() -> Access {
+        Access::default()
+    }
+
+    pub fn read<T: Component + 'static>(mut self) -> Self {
+        self.reads.push(AccessType::of::<T>());
+        self
+    }
+
+    pub fn write<T: Component + 'static>(mut self) -> Self {
+        self.writes.push(AccessType::of::<T>());
+        self
+    }
+
+    pub fn read_resource<R: Resource>(mut self) -> Self {
+        self.reads.push(AccessType::of::<R>());
+        self
+    }
+
+    pub fn write_resource<R: Resource>(mut self) -> Self {
+        self.writes.push(AccessType::of::<R>());
+        self
+    }
+
+    //Name of the first type written by one side and touched by the other
+    fn conflict(&self, other: &Access) -> Option<&'static str> {
+        let touches = |access: &Access, id: TypeId| {
+            access.reads.iter().chain(access.writes.iter()).any(|ty| ty.id == id)
+        };
+
+        self.writes.iter().find(|ty| touches(other, ty.id))
+            .or_else(|| other.writes.iter().find(|ty| touches(self, ty.id)))
+            .map(|ty| ty.name)
+    }
+}
+
+//Systems only get shared access to the Ecs so that a stage can run them side by side,
+//the stores they declared in access() are borrowed at runtime
+pub trait System: Send {
+    fn name(&self) -> &str;
+    fn access(&self) -> Access;
+    fn run(&mut self, ecs: &Ecs);
+}
+
+//Wraps a closure so it can be scheduled without writing a System impl
+pub struct FnSystem<F> {
+    name: String,
+    access: Access,
+    func: F,
+}
+
+impl<F: FnMut(&Ecs) + Send> FnSystem<F> {
+    pub fn new(name: &str, access: Access, func: F) -> FnSystem<F> {
+        FnSystem {
+            name: name.to_string(),
+            access,
+            func,
+        }
+    }
+}
+
+impl<F: FnMut(&Ecs) + Send> System for FnSystem<F> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn access(&self) -> Access {
+        self.access.clone()
+    }
+
+    fn run(&mut self, ecs: &Ecs) {
+        (self.func)(ecs)
+    }
+}
+
+//Runs alone with the whole Ecs, e.g. to create and delete entities
+pub struct ExclusiveSystem {
+    name: String,
+    func: Box<dyn FnMut(&mut Ecs)>,
+    last_run: u32,
+}
+
+impl ExclusiveSystem {
+    pub fn new<F: FnMut(&mut Ecs) + 'static>(name: &str, func: F) -> ExclusiveSystem {
+        ExclusiveSystem {
+            name: name.to_string(),
+            func: Box::new(func),
+            last_run: 0,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&mut self, ecs: &mut Ecs) {
+        self.last_run = ecs.start_run(self.last_run);
+        (self.func)(ecs)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Executor {
+    //Every system of a stage runs on the rayon thread pool at the same time
+    Parallel,
+    //Systems run one after another in the order they were added, useful for debugging
+    SingleThreaded,
+}
+
+#[derive(Default)]
+struct StageSystems {
+    exclusive: Vec<ExclusiveSystem>,
+    systems: Vec<Box<dyn System>>,
+    //the systems of a stage never write what another one reads, so they can share one change tick
+    last_run: u32,
+}
+
+#[derive(Fail, Debug)]
+pub enum ScheduleError {
+    #[fail(display = "systems {} and {} in stage {:?} conflict on {}", _0, _1, _2, _3)]
+    Conflict(String, String, Stage, &'static str),
+}
+
+pub struct ScheduleBuilder {
+    stages: Vec<(Stage, StageSystems)>,
+    executor: Executor,
+}
+
+impl ScheduleBuilder {
+    fn stage(&mut self, stage: Stage) -> &mut StageSystems {
+        let position = match self.stages.iter().position(|(s, _)| *s == stage) {
+            Some(position) => position,
+            None => {
+                self.stages.push((stage, StageSystems::default()));
+                self.stages.len() - 1
+            }
+        };
+        &mut self.stages[position].1
+    }
+
+    pub fn add_system<S: System + 'static>(self, system: S) -> Self {
+        self.add_system_to_stage(Stage::Update, system)
+    }
+
+    pub fn add_system_to_stage<S: System + 'static>(mut self, stage: Stage, system: S) -> Self {
+        self.stage(stage).systems.push(Box::new(system));
+        self
+    }
+
+    //Exclusive systems run before the other systems of their stage
+    pub fn add_exclusive_system_to_stage(mut self, stage: Stage, system: ExclusiveSystem) -> Self {
+        self.stage(stage).exclusive.push(system);
+        self
+    }
+
+    pub fn executor(mut self, executor: Executor) -> Self {
+        self.executor = executor;
+        self
+    }
+
+    //Systems sharing a stage may run at the same time, so none of them may write a component type
+    //another one of them touches
+    pub fn build(mut self) -> Result<Schedule, ScheduleError> {
+        self.stages.sort_by_key(|(stage, _)| *stage);
+
+        for (stage, stage_systems) in &self.stages {
+            let systems = &stage_systems.systems;
+            let accesses: Vec<Access> = systems.iter().map(|system| system.access()).collect();
+            for (i, access) in accesses.iter().enumerate() {
+                for (j, other) in accesses.iter().enumerate().skip(i + 1) {
+                    if let Some(ty) = access.conflict(other) {
+                        return Err(ScheduleError::Conflict(
+                            systems[i].name().to_string(),
+                            systems[j].name().to_string(),
+                            *stage,
+                            ty,
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(Schedule {
+            stages: self.stages,
+            executor: self.executor,
+            started: false,
+            ticked: false,
+        })
+    }
+}
+
+pub struct Schedule {
+    stages: Vec<(Stage, StageSystems)>,
+    executor: Executor,
+    started: bool,
+    //a tick ran with run_fixed since events were last swapped
+    ticked: bool,
+}
+
+impl Schedule {
+    pub fn builder() -> ScheduleBuilder {
+        ScheduleBuilder {
+            stages: Vec::new(),
+            executor: Executor::Parallel,
+        }
+    }
+
+    pub fn set_executor(&mut self, executor: Executor) {
+        self.executor = executor;
+    }
+
+    fn run_stage(&mut self, stage: Stage, ecs: &mut Ecs) {
+        let executor = self.executor;
+        if let Some((_, stage_systems)) = self.stages.iter_mut().find(|(s, _)| *s == stage) {
+            for system in stage_systems.exclusive.iter_mut() {
+                system.run(ecs);
+            }
+
+            stage_systems.last_run = ecs.start_run(stage_systems.last_run);
+            let shared = &*ecs;
+            match executor {
+                Executor::Parallel => stage_systems.systems.par_iter_mut().for_each(|system| system.run(shared)),
+                Executor::SingleThreaded => stage_systems.systems.iter_mut().for_each(|system| system.run(shared)),
+            }
+
+            //sync point, structural changes recorded during the stage are visible to the next one
+            ecs.apply_commands();
+        }
+    }
+
+    fn start(&mut self, ecs: &mut Ecs) {
+        if !self.started {
+            self.run_stage(Stage::Startup, ecs);
+            self.started = true;
+        }
+    }
+
+    fn end_frame(&mut self, ecs: &mut Ecs) {
+        //ticks wrap around, so the oldest run is the one furthest back from now rather than the smallest.
+        //Stages that never ran don't hold anything back, like Render when only run_fixed is called.
+        let change_tick = ecs.change_tick;
+        let oldest_run = self.stages.iter()
+            .filter(|(stage, _)| *stage != Stage::Startup)
+            .flat_map(|(_, systems)| systems.exclusive.iter().map(|system| system.last_run).chain(Some(systems.last_run)))
+            .filter(|last_run| *last_run != 0)
+            .max_by_key(|last_run| change_tick.wrapping_sub(*last_run));
+        ecs.end_frame(oldest_run.unwrap_or(change_tick));
+    }
+
+    //Runs one frame, the startup stage is run first the very first time
+    pub fn run(&mut self, ecs: &mut Ecs) {
+        self.start(ecs);
+        for stage in Stage::FRAME.iter() {
+            self.run_stage(*stage, ecs);
+        }
+        self.end_frame(ecs);
+        ecs.update_events();
+        self.ticked = false;
+    }
+
+    //Runs one simulation tick without the Render stage. Events aren't swapped per tick but by finish_frame,
+    //so systems of every stage see them however many ticks a frame runs.
+    pub fn run_fixed(&mut self, ecs: &mut Ecs) {
+        self.start(ecs);
+        for stage in Stage::FIXED.iter() {
+            self.run_stage(*stage, ecs);
+        }
+        self.end_frame(ecs);
+        self.ticked = true;
+    }
+
+    //Runs the Render stage alone, once per drawn frame when ticks are run with run_fixed
+    pub fn run_render(&mut self, ecs: &mut Ecs) {
+        self.start(ecs);
+        self.run_stage(Stage::Render, ecs);
+    }
+
+    //Ends a frame of run_fixed ticks. Events are swapped only if a tick ran since the last swap, so events
+    //sent during frames without one wait for the next tick.
+    pub fn finish_frame(&mut self, ecs: &mut Ecs) {
+        if std::mem::replace(&mut self.ticked, false) {
+            ecs.update_events();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    struct Pos(i32);
+    impl Component for Pos {}
+
+    struct Vel(i32);
+    impl Component for Vel {}
+
+    fn system(name: &str, access: Access) -> FnSystem<impl FnMut(&Ecs) + Send> {
+        FnSystem::new(name, access, |_: &Ecs| {})
+    }
+
+    #[test]
+    fn conflicting_systems_in_one_stage() {
+        let result = Schedule::builder()
+            .add_system(system("move", Access::new().read::<Vel>().write::<Pos>()))
+            .add_system(system("teleport", Access::new().write::<Pos>()))
+            .build();
+        match result {
+            Err(ScheduleError::Conflict(first, second, stage, ty)) => {
+                assert_eq!((first.as_str(), second.as_str(), stage), ("move", "teleport", Stage::Update));
+                assert_eq!(ty, type_name::<Pos>());
+            }
+            Ok(_) => panic!("two writers of Pos were scheduled in the same stage"),
+        }
+
+        //a reader conflicts with a writer just the same, whichever was added first
+        let result = Schedule::builder()
+            .add_system_to_stage(Stage::PostUpdate, system("draw", Access::new().read::<Pos>()))
+            .add_system_to_stage(Stage::PostUpdate, system("move", Access::new().write::<Pos>()))
+            .build();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn systems_in_different_stages_dont_conflict() {
+        let result = Schedule::builder()
+            .add_system_to_stage(Stage::PreUpdate, system("move", Access::new().write::<Pos>()))
+            .add_system_to_stage(Stage::Update, system("teleport", Access::new().write::<Pos>()))
+            .add_system_to_stage(Stage::Update, system("accelerate", Access::new().write::<Vel>()))
+            .add_system_to_stage(Stage::Render, system("draw", Access::new().read::<Pos>().read::<Vel>()))
+            .add_system_to_stage(Stage::Render, system("debug", Access::new().read::<Pos>()))
+            .build();
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn single_threaded_runs_in_order() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let record = |name: &'static str| {
+            let order = order.clone();
+            FnSystem::new(name, Access::new(), move |_: &Ecs| order.lock().unwrap().push(name))
+        };
+        let exclusive_order = order.clone();
+
+        let mut schedule = Schedule::builder()
+            .add_system_to_stage(Stage::Render, record("render"))
+            .add_system(record("first"))
+            .add_system(record("second"))
+            .add_system(record("third"))
+            .add_exclusive_system_to_stage(Stage::Update, ExclusiveSystem::new("exclusive", move |_: &mut Ecs| {
+                exclusive_order.lock().unwrap().push("exclusive")
+            }))
+            .add_system_to_stage(Stage::PreUpdate, record("pre"))
+            .executor(Executor::SingleThreaded)
+            .build()
+            .unwrap();
+
+        let mut ecs = Ecs::new();
+        schedule.run(&mut ecs);
+        schedule.run(&mut ecs);
+        let frame = ["pre", "exclusive", "first", "second", "third", "render"];
+        let expected: Vec<&str> = frame.iter().chain(frame.iter()).cloned().collect();
+        assert_eq!(*order.lock().unwrap(), expected);
+    }
+}
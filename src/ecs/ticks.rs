@@ -0,0 +1,133 @@
+use super::Handle;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+//Ticks are compared by how many ticks ago they happened, so the counter can wrap around.
+//Every CHECK_TICK_THRESHOLD ticks the Ecs clamps ticks older than MAX_CHANGE_AGE to that age,
+//so no age ever gets near u32::MAX and an old change never looks new again.
+pub(super) const CHECK_TICK_THRESHOLD: u32 = 1 << 30;
+pub(super) const MAX_CHANGE_AGE: u32 = 1 << 31;
+
+//Whether tick happened after last_run, both seen from this_run
+pub(super) fn is_newer(tick: u32, last_run: u32, this_run: u32) -> bool {
+    let last_age = this_run.wrapping_sub(last_run).min(MAX_CHANGE_AGE);
+    this_run.wrapping_sub(tick) < last_age
+}
+
+//0 is skipped, it marks a missing component
+pub(super) fn next_tick(tick: u32) -> u32 {
+    match tick.wrapping_add(1) {
+        0 => 1,
+        tick => tick,
+    }
+}
+
+//The oldest tick that doesn't need clamping
+fn clamped_tick(this_run: u32) -> u32 {
+    match this_run.wrapping_sub(MAX_CHANGE_AGE) {
+        0 => 1,
+        tick => tick,
+    }
+}
+
+//Clamps a tick older than MAX_CHANGE_AGE, returns false if it was
+pub(super) fn check_tick(tick: &mut u32, this_run: u32) -> bool {
+    if this_run.wrapping_sub(*tick) > MAX_CHANGE_AGE {
+        *tick = clamped_tick(this_run);
+        false
+    } else {
+        true
+    }
+}
+
+//When each entity's component of one type was added and last changed, indexed by entity index.
+//Ticks are atomics so queries running in parallel can mark changes without locking the store,
+//0 means the entity doesn't have the component.
+pub struct ComponentTicks {
+    added: Vec<AtomicU32>,
+    changed: Vec<AtomicU32>,
+}
+
+impl ComponentTicks {
+    pub fn new() -> ComponentTicks {
+        ComponentTicks {
+            added: Vec::new(),
+            changed: Vec::new(),
+        }
+    }
+
+    fn load(ticks: &[AtomicU32], entity: &Handle) -> u32 {
+        ticks.get(entity.index()).map_or(0, |tick| tick.load(Ordering::Relaxed))
+    }
+
+    pub fn insert(&mut self, entity: &Handle, tick: u32) {
+        if self.added.len() <= entity.index() {
+            self.added.resize_with(entity.index() + 1, || AtomicU32::new(0));
+            self.changed.resize_with(entity.index() + 1, || AtomicU32::new(0));
+        }
+        *self.added[entity.index()].get_mut() = tick;
+        *self.changed[entity.index()].get_mut() = tick;
+    }
+
+    pub fn remove(&mut self, entity: &Handle) {
+        if let Some(tick) = self.added.get_mut(entity.index()) {
+            *tick.get_mut() = 0;
+            *self.changed[entity.index()].get_mut() = 0;
+        }
+    }
+
+    pub fn set_changed(&self, entity: &Handle, tick: u32) {
+        if let Some(changed) = self.changed.get(entity.index()) {
+            changed.store(tick, Ordering::Relaxed);
+        }
+    }
+
+    //See CHECK_TICK_THRESHOLD
+    pub(super) fn check(&mut self, this_run: u32) {
+        for tick in self.added.iter_mut().chain(self.changed.iter_mut()) {
+            let tick = tick.get_mut();
+            if *tick != 0 {
+                check_tick(tick, this_run);
+            }
+        }
+    }
+
+    pub fn contains(&self, entity: &Handle) -> bool {
+        self.added(entity) != 0
+    }
+
+    pub fn added(&self, entity: &Handle) -> u32 {
+        Self::load(&self.added, entity)
+    }
+
+    pub fn changed(&self, entity: &Handle) -> u32 {
+        Self::load(&self.changed, entity)
+    }
+}
+
+//Entities that lost their T, after the running system last ran
+pub struct RemovedComponents<'w, T> {
+    removed: std::slice::Iter<'w, (Handle, u32)>,
+    last_run: u32,
+    this_run: u32,
+    marker: std::marker::PhantomData<T>,
+}
+
+impl<'w, T> RemovedComponents<'w, T> {
+    pub(super) fn new(removed: &'w [(Handle, u32)], last_run: u32, this_run: u32) -> RemovedComponents<'w, T> {
+        RemovedComponents {
+            removed: removed.iter(),
+            last_run,
+            this_run,
+            marker: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<'w, T> Iterator for RemovedComponents<'w, T> {
+    type Item = &'w Handle;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (last_run, this_run) = (self.last_run, self.this_run);
+        self.removed.find(|(_, removed)| is_newer(*removed, last_run, this_run)).map(|(entity, _)| entity)
+    }
+}
@@ -0,0 +1,81 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+
+//A raw id tagged with the type of what it points to,
+//so a texture handle can't be passed where a mesh handle is expected
+pub struct Handle<T, R> {
+    raw: R,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T, R: Copy> Handle<T, R> {
+    pub fn new(raw: R) -> Handle<T, R> {
+        Handle {
+            raw,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn raw(&self) -> R {
+        self.raw
+    }
+}
+
+//Implemented by hand so T doesn't need to implement them too
+impl<T, R: Copy> Clone for Handle<T, R> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, R: Copy> Copy for Handle<T, R> {}
+
+impl<T, R: PartialEq> PartialEq for Handle<T, R> {
+    fn eq(&self, other: &Self) -> bool {
+        self.raw == other.raw
+    }
+}
+
+impl<T, R: Eq> Eq for Handle<T, R> {}
+
+impl<T, R: PartialOrd> PartialOrd for Handle<T, R> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.raw.partial_cmp(&other.raw)
+    }
+}
+
+impl<T, R: Ord> Ord for Handle<T, R> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.raw.cmp(&other.raw)
+    }
+}
+
+impl<T, R: Hash> Hash for Handle<T, R> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.raw.hash(state)
+    }
+}
+
+impl<T, R: Serialize> Serialize for Handle<T, R> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.raw.serialize(serializer)
+    }
+}
+
+impl<'de, T, R: Deserialize<'de>> Deserialize<'de> for Handle<T, R> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Handle<T, R>, D::Error> {
+        R::deserialize(deserializer).map(|raw| Handle {
+            raw,
+            marker: PhantomData,
+        })
+    }
+}
+
+impl<T, R: fmt::Debug> fmt::Debug for Handle<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Handle<{}>({:?})", std::any::type_name::<T>(), self.raw)
+    }
+}
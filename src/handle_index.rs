@@ -1,48 +1,217 @@
-pub type Handle = usize;
+use std::convert::TryFrom;
+use crate::dense::{self, Dense, DenseContainer};
 
+//Handle to a value of a HandleVec<T, H>
+pub type Handle<T, H = usize> = crate::handle::Handle<T, H>;
+
+pub type Iter<'a, T, H = usize> = dense::Iter<'a, T, H>;
+pub type IterMut<'a, T, H = usize> = dense::IterMut<'a, T, H>;
+pub type Drain<'a, T, H = usize> = dense::Drain<'a, HandleVec<T, H>>;
+
+//What a HandleVec handle holds. Plain usize handles are only a slot,
+//VersionedHandle also carries the slot's generation so a recycled slot doesn't pass for the old one.
+pub trait HandleKind : Copy + Ord + std::hash::Hash + std::fmt::Debug {
+    fn new(slot : usize, generation : u32) -> Self;
+    fn slot(self) -> usize;
+    //None skips the generation check
+    fn generation(self) -> Option<u32>;
+}
+
+impl HandleKind for usize {
+    fn new(slot : usize, _generation : u32) -> usize {
+        slot
+    }
+
+    fn slot(self) -> usize {
+        self
+    }
+
+    fn generation(self) -> Option<u32> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VersionedHandle {
+    slot : u32,
+    generation : u32
+}
+
+impl HandleKind for VersionedHandle {
+    fn new(slot : usize, generation : u32) -> VersionedHandle {
+        VersionedHandle { slot : u32::try_from(slot).expect("HandleVec has more slots than a VersionedHandle can address"), generation }
+    }
+
+    fn slot(self) -> usize {
+        self.slot as usize
+    }
+
+    fn generation(self) -> Option<u32> {
+        Some(self.generation)
+    }
+}
+
+//Both hold the slot's generation, it goes up every time the slot is freed
 enum Entry {
-    Value(usize),
-    Empty(usize)
+    Value(usize, u32),
+    Empty(usize, u32),
+    //the generation ran out, HandleVec skips the slot from then on
+    Retired
 }
 
-pub struct HandleVec<T>{
-    values : Vec<T>,
+pub struct HandleVec<T, H : HandleKind = usize>{
+    values : Dense<T, H>,
     handles : Vec<Entry>,
-    next : Handle
+    next : usize
 }
 
-impl<T> HandleVec<T>{
-    pub fn new() -> HandleVec<T> {
+impl<T, H : HandleKind> HandleVec<T, H>{
+    pub fn new() -> HandleVec<T, H> {
+        HandleVec::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity : usize) -> HandleVec<T, H> {
+        let mut handles = Vec::with_capacity(capacity + 1);
+        handles.push(Entry::Empty(1, 0));
         HandleVec {
-            values : Vec::new(),
-            handles : vec![Entry::Empty(1)],
+            values : Dense::with_capacity(capacity),
+            handles,
             next : 0
         }
     }
 
-    pub fn get(&self, handle : Handle) -> Option<&T> {
-        if let Some(&Entry::Value(index)) = self.handles.get(handle){
-            self.values.get(index)
-        } else {
-            None
+    //Position of the handle's value, if the handle is still valid
+    fn position(&self, handle : Handle<T, H>) -> Option<usize> {
+        let handle = handle.raw();
+        match self.handles.get(handle.slot()) {
+            Some(&Entry::Value(index, generation)) if handle.generation().is_none_or(|g| g == generation) => Some(index),
+            _ => None
         }
     }
 
-    pub fn insert(&mut self, value : T) -> Handle {
+    pub fn get(&self, handle : Handle<T, H>) -> Option<&T> {
+        self.position(handle).map(|index| self.values.get(index))
+    }
 
-        self.values.push(value);
-        let index = self.values.len()-1;
+    pub fn get_mut(&mut self, handle : Handle<T, H>) -> Option<&mut T> {
+        self.position(handle).map(move |index| self.values.get_mut(index))
+    }
+
+    pub fn insert(&mut self, value : T) -> Handle<T, H> {
 
-        let res = self.next; //res is the newly returned handle
-        self.next = match self.handles[res] { Entry::Empty(index) => index,
-                                              Entry::Value(_) => panic!("handle_index found Value instead of Empty") }; //next becomes the next open handle
+        let slot = self.next; //slot of the newly returned handle
+        let generation;
+        self.next = match self.handles[slot] { Entry::Empty(next, gen) => { generation = gen; next },
+                                               _ => panic!("handle_index found a used slot in the free list") }; //next becomes the next open handle
 
-        self.handles[res] = Entry::Value(index);
+        let res = Handle::new(H::new(slot, generation));
+        let index = self.values.push(res.raw(), value);
+        self.handles[slot] = Entry::Value(index, generation);
 
         if self.next >= self.handles.len() {
-            self.handles.push(Entry::Empty(self.handles.len()+1));
+            self.handles.push(Entry::Empty(self.handles.len()+1, 0));
         }
 
         res
     }
-}
\ No newline at end of file
+
+    //Returns None if the handle was already removed, or its slot was reused when generations are checked
+    pub fn remove(&mut self, handle : Handle<T, H>) -> Option<T> {
+        self.position(handle).map(|index| self.remove_at(index).1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.drain();
+    }
+
+    pub fn drain(&mut self) -> Drain<'_, T, H> {
+        Drain::new(self)
+    }
+
+    pub fn retain<F : FnMut(Handle<T, H>, &mut T) -> bool>(&mut self, keep : F) {
+        dense::retain(self, keep)
+    }
+
+    pub fn iter(&self) -> Iter<'_, T, H> {
+        self.values.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, T, H> {
+        self.values.iter_mut()
+    }
+}
+
+impl<T, H : HandleKind> DenseContainer for HandleVec<T, H> {
+    type Value = T;
+    type Raw = H;
+
+    fn dense_mut(&mut self) -> &mut Dense<T, H> {
+        &mut self.values
+    }
+
+    //The slot goes back on the free list with its generation bumped
+    fn remove_at(&mut self, index : usize) -> (Handle<T, H>, T) {
+        let (handle, value) = self.values.swap_remove(index);
+        if let Some(moved) = self.values.owner(index) {
+            if let Entry::Value(position, _) = &mut self.handles[moved.slot()] {
+                *position = index;
+            }
+        }
+
+        let slot = handle.slot();
+        let generation = match self.handles[slot] { Entry::Value(_, gen) => gen.checked_add(1),
+                                                    _ => panic!("handle_index removed a value from a free slot") };
+        match generation {
+            Some(generation) => {
+                self.handles[slot] = Entry::Empty(self.next, generation);
+                self.next = slot;
+            },
+            None => self.handles[slot] = Entry::Retired
+        }
+        (Handle::new(handle), value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_versioned_handle() {
+        let mut vec : HandleVec<u32, VersionedHandle> = HandleVec::new();
+        let old = vec.insert(1);
+        assert_eq!(vec.remove(old), Some(1));
+        let new = vec.insert(2);
+
+        assert_eq!(old.raw().slot(), new.raw().slot());
+        assert_eq!(vec.get(old), None);
+        assert_eq!(vec.remove(old), None);
+        assert_eq!(vec.get(new), Some(&2));
+    }
+
+    #[test]
+    fn saturated_slot_is_retired() {
+        let mut vec : HandleVec<u32, VersionedHandle> = HandleVec::new();
+        let first = vec.insert(1);
+        let slot = first.raw().slot();
+        if let Entry::Value(_, generation) = &mut vec.handles[slot] {
+            *generation = u32::MAX;
+        }
+        let last = Handle::new(VersionedHandle::new(slot, u32::MAX));
+
+        assert_eq!(vec.remove(last), Some(1));
+        let next = vec.insert(2);
+        assert_ne!(next.raw().slot(), slot);
+        assert_eq!(vec.get(last), None);
+        assert_eq!(vec.get(first), None);
+        assert_eq!(vec.get(next), Some(&2));
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::HashSet;
+use std::hash::Hash;
+use winit::{DeviceEvent, ElementState, Event, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent};
+use crate::actions::ActionMap;
+use crate::ecs::Ecs;
+use crate::ecs::events::{EventReader, Events};
+use crate::ecs::system::{Access, System};
+
+//Which buttons are held, and which went down or up since the last frame
+pub struct Buttons<T : Copy + Eq + Hash> {
+    pressed : HashSet<T>,
+    just_pressed : HashSet<T>,
+    just_released : HashSet<T>
+}
+
+impl<T : Copy + Eq + Hash> Buttons<T> {
+    pub fn new() -> Buttons<T> {
+        Buttons {
+            pressed : HashSet::new(),
+            just_pressed : HashSet::new(),
+            just_released : HashSet::new()
+        }
+    }
+
+    //Key repeats while a button is held don't count as a new press
+    pub fn press(&mut self, button : T) {
+        if self.pressed.insert(button) {
+            self.just_pressed.insert(button);
+        }
+    }
+
+    pub fn release(&mut self, button : T) {
+        if self.pressed.remove(&button) {
+            self.just_released.insert(button);
+        }
+    }
+
+    pub fn release_all(&mut self) {
+        self.just_released.extend(self.pressed.drain());
+    }
+
+    pub fn pressed(&self, button : T) -> bool {
+        self.pressed.contains(&button)
+    }
+
+    pub fn just_pressed(&self, button : T) -> bool {
+        self.just_pressed.contains(&button)
+    }
+
+    pub fn just_released(&self, button : T) -> bool {
+        self.just_released.contains(&button)
+    }
+
+    pub fn iter_pressed(&self) -> impl Iterator<Item = &T> {
+        self.pressed.iter()
+    }
+
+    pub fn iter_just_pressed(&self) -> impl Iterator<Item = &T> {
+        self.just_pressed.iter()
+    }
+
+    fn clear_frame(&mut self) {
+        self.just_pressed.clear();
+        self.just_released.clear();
+    }
+}
+
+impl<T : Copy + Eq + Hash> Default for Buttons<T> {
+    fn default() -> Buttons<T> {
+        Buttons::new()
+    }
+}
+
+//Keyboard and mouse state built from winit events, kept in the Ecs as a resource by InputSystem.
+//Deltas add up the events of one frame.
+#[derive(Default)]
+pub struct Input {
+    pub keys : Buttons<VirtualKeyCode>,
+    pub mouse_buttons : Buttons<MouseButton>,
+    //logical pixels from the top left of the window, None while the cursor is outside of it
+    cursor : Option<(f64, f64)>,
+    scroll_lines : (f32, f32),
+    scroll_pixels : (f64, f64),
+    //raw device motion, not tied to the cursor or the window
+    mouse_motion : (f64, f64)
+}
+
+impl Input {
+    pub fn new() -> Input {
+        Input::default()
+    }
+
+    //Forgets what happened last frame, call before feeding the events of a new one
+    pub fn clear_frame(&mut self) {
+        self.keys.clear_frame();
+        self.mouse_buttons.clear_frame();
+        self.scroll_lines = (0.0, 0.0);
+        self.scroll_pixels = (0.0, 0.0);
+        self.mouse_motion = (0.0, 0.0);
+    }
+
+    pub fn handle_event(&mut self, event : &Event) {
+        match event {
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::KeyboardInput { input, .. } => {
+                    if let Some(key) = input.virtual_keycode {
+                        match input.state {
+                            ElementState::Pressed => self.keys.press(key),
+                            ElementState::Released => self.keys.release(key)
+                        }
+                    }
+                },
+
+                WindowEvent::MouseInput { state, button, .. } => match state {
+                    ElementState::Pressed => self.mouse_buttons.press(*button),
+                    ElementState::Released => self.mouse_buttons.release(*button)
+                },
+
+                WindowEvent::CursorMoved { position, .. } => {
+                    self.cursor = Some((position.x, position.y));
+                },
+
+                WindowEvent::CursorLeft { .. } => {
+                    self.cursor = None;
+                },
+
+                WindowEvent::MouseWheel { delta, .. } => match delta {
+                    MouseScrollDelta::LineDelta(x, y) => {
+                        self.scroll_lines.0 += x;
+                        self.scroll_lines.1 += y;
+                    },
+                    MouseScrollDelta::PixelDelta(position) => {
+                        self.scroll_pixels.0 += position.x;
+                        self.scroll_pixels.1 += position.y;
+                    }
+                },
+
+                //releases that happen while another window has focus never arrive
+                WindowEvent::Focused(false) => {
+                    self.keys.release_all();
+                    self.mouse_buttons.release_all();
+                },
+
+                _ => ()
+            },
+
+            Event::DeviceEvent { event : DeviceEvent::MouseMotion { delta }, .. } => {
+                self.mouse_motion.0 += delta.0;
+                self.mouse_motion.1 += delta.1;
+            },
+
+            _ => ()
+        }
+    }
+
+    pub fn cursor_position(&self) -> Option<(f64, f64)> {
+        self.cursor
+    }
+
+    //Positive is away from the user and to the right
+    pub fn scroll_lines(&self) -> (f32, f32) {
+        self.scroll_lines
+    }
+
+    //Touchpads scroll by pixels instead of lines
+    pub fn scroll_pixels(&self) -> (f64, f64) {
+        self.scroll_pixels
+    }
+
+    pub fn mouse_motion(&self) -> (f64, f64) {
+        self.mouse_motion
+    }
+}
+
+//Updates the Input resource from the window events sent since its last run, and the ActionMap from the Input
+#[derive(Default)]
+pub struct InputSystem {
+    reader : EventReader<Event>
+}
+
+impl InputSystem {
+    pub fn new() -> InputSystem {
+        InputSystem::default()
+    }
+}
+
+impl System for InputSystem {
+    fn name(&self) -> &str {
+        "input"
+    }
+
+    fn access(&self) -> Access {
+        Access::new()
+            .read_resource::<Events<Event>>()
+            .write_resource::<Input>()
+            .write_resource::<ActionMap>()
+    }
+
+    fn run(&mut self, ecs : &Ecs) {
+        let (events, mut input) = match (ecs.resource::<Events<Event>>(), ecs.resource_mut::<Input>()) {
+            (Some(events), Some(input)) => (events, input),
+            _ => return
+        };
+
+        input.clear_frame();
+        for event in self.reader.iter(&events) {
+            input.handle_event(event);
+        }
+        if let Some(mut actions) = ecs.resource_mut::<ActionMap>() {
+            actions.update(&input);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use winit::{DeviceId, KeyboardInput, ModifiersState, WindowId};
+
+    pub(crate) fn window_event(event : WindowEvent) -> Event {
+        Event::WindowEvent { window_id : unsafe { WindowId::dummy() }, event }
+    }
+
+    pub(crate) fn key(key : VirtualKeyCode, state : ElementState) -> Event {
+        window_event(WindowEvent::KeyboardInput {
+            device_id : unsafe { DeviceId::dummy() },
+            input : KeyboardInput { scancode : 0, state, virtual_keycode : Some(key), modifiers : ModifiersState::default() }
+        })
+    }
+
+    pub(crate) fn mouse_button(button : MouseButton, state : ElementState) -> Event {
+        window_event(WindowEvent::MouseInput { device_id : unsafe { DeviceId::dummy() }, state, button, modifiers : ModifiersState::default() })
+    }
+
+    pub(crate) fn mouse_motion(x : f64, y : f64) -> Event {
+        Event::DeviceEvent { device_id : unsafe { DeviceId::dummy() }, event : DeviceEvent::MouseMotion { delta : (x, y) } }
+    }
+
+    //What InputSystem does once per frame, the actions tests drive Input with these helpers too
+    pub(crate) fn update(input : &mut Input, events : &[Event]) {
+        input.clear_frame();
+        for event in events {
+            input.handle_event(event);
+        }
+    }
+
+    #[test]
+    fn key_press_and_release() {
+        let mut input = Input::new();
+        update(&mut input, &[key(VirtualKeyCode::W, ElementState::Pressed)]);
+        assert!(input.keys.pressed(VirtualKeyCode::W));
+        assert!(input.keys.just_pressed(VirtualKeyCode::W));
+
+        //a key repeat is not a new press
+        update(&mut input, &[key(VirtualKeyCode::W, ElementState::Pressed)]);
+        assert!(input.keys.pressed(VirtualKeyCode::W));
+        assert!(!input.keys.just_pressed(VirtualKeyCode::W));
+
+        update(&mut input, &[key(VirtualKeyCode::W, ElementState::Released)]);
+        assert!(!input.keys.pressed(VirtualKeyCode::W));
+        assert!(input.keys.just_released(VirtualKeyCode::W));
+
+        update(&mut input, &[]);
+        assert!(!input.keys.just_released(VirtualKeyCode::W));
+    }
+
+    #[test]
+    fn mouse_button_press_and_release() {
+        let mut input = Input::new();
+        update(&mut input, &[mouse_button(MouseButton::Left, ElementState::Pressed)]);
+        assert!(input.mouse_buttons.pressed(MouseButton::Left));
+        assert!(input.mouse_buttons.just_pressed(MouseButton::Left));
+        assert!(!input.mouse_buttons.pressed(MouseButton::Right));
+
+        update(&mut input, &[]);
+        assert!(input.mouse_buttons.pressed(MouseButton::Left));
+        assert!(!input.mouse_buttons.just_pressed(MouseButton::Left));
+
+        //pressed and released within one frame
+        update(&mut input, &[
+            mouse_button(MouseButton::Left, ElementState::Released),
+            mouse_button(MouseButton::Right, ElementState::Pressed),
+            mouse_button(MouseButton::Right, ElementState::Released)
+        ]);
+        assert!(input.mouse_buttons.just_released(MouseButton::Left));
+        assert!(input.mouse_buttons.just_pressed(MouseButton::Right));
+        assert!(input.mouse_buttons.just_released(MouseButton::Right));
+        assert!(!input.mouse_buttons.pressed(MouseButton::Right));
+
+        update(&mut input, &[]);
+        assert!(!input.mouse_buttons.just_released(MouseButton::Left));
+        assert!(!input.mouse_buttons.just_pressed(MouseButton::Right));
+    }
+
+    #[test]
+    fn focus_loss_releases_everything() {
+        let mut input = Input::new();
+        update(&mut input, &[key(VirtualKeyCode::A, ElementState::Pressed)]);
+        update(&mut input, &[window_event(WindowEvent::Focused(false))]);
+        assert!(!input.keys.pressed(VirtualKeyCode::A));
+        assert!(input.keys.just_released(VirtualKeyCode::A));
+    }
+
+    #[test]
+    fn mouse_motion_adds_up_per_frame() {
+        let mut input = Input::new();
+        update(&mut input, &[mouse_motion(1.0, 2.0), mouse_motion(3.0, -5.0)]);
+        assert_eq!(input.mouse_motion(), (4.0, -3.0));
+
+        update(&mut input, &[]);
+        assert_eq!(input.mouse_motion(), (0.0, 0.0));
+
+        update(&mut input, &[mouse_motion(-1.5, 0.5)]);
+        assert_eq!(input.mouse_motion(), (-1.5, 0.5));
+    }
+}
@@ -3,14 +3,22 @@
 #[macro_use] extern crate failure_derive;
 
 mod ecs;
+mod handle;
+mod dense;
 mod handle_index;
 mod window;
+mod input;
+mod actions;
 mod renderer;
+mod app;
 
 
 use ecs::Ecs;
-use window::Window;
-use renderer::Renderer;
+use ecs::system::{ExclusiveSystem, Schedule, Stage};
+use window::WindowConfig;
+use input::{Input, InputSystem};
+use actions::ActionMap;
+use app::App;
 
 #[derive(Debug)]
 struct Point {
@@ -19,22 +27,40 @@ struct Point {
 }
 impl ecs::Component for Point {}
 
-fn main() {
-    let mut s = Ecs::new();
+fn spawn_points(s: &mut Ecs) {
     let entity = s.create_entity();
     let point = Point{x:1, y:2};
-    let ok = s.add_comp(&entity, point);
-    println!("{}", ok);
-    let ok = s.add_comp(&entity, Point { x: 3, y: 8 });
-    println!("{}", ok);
+    let old = s.add_comp(&entity, point);
+    println!("{:?}", old);
+    let old = s.add_comp(&entity, Point { x: 3, y: 8 });
+    println!("{:?}", old);
 
     let p = s.get_comp::<Point>(&entity).unwrap();
     println!("{:?}", p);
+}
+
+fn main() {
+    //RUST_LOG picks what is printed, info and up by default
+    env_logger::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let schedule = Schedule::builder()
+        .add_exclusive_system_to_stage(Stage::Startup, ExclusiveSystem::new("spawn_points", spawn_points))
+        .add_system_to_stage(Stage::PreUpdate, InputSystem::new())
+        .build()
+        .expect("invalid schedule");
+
+    let config = WindowConfig::load("assets/window.json").unwrap_or_else(|err| {
+        log::warn!("No window settings: {}", err);
+        WindowConfig::new("window")
+    });
+    let mut app = App::new(config, schedule).with_clear_colour([1.0,0.0,0.0,1.0]);
 
-    let mut w = Window::new("window");
-    let mut r = Renderer::new(&w);
-    while w.poll_events() {
-        r.draw_clear_colour([1.0,0.0,0.0,1.0]).expect("clear colour failed");
-    }
+    app.ecs_mut().insert_resource(Input::new());
+    let actions = ActionMap::load("assets/actions.json").unwrap_or_else(|err| {
+        log::warn!("No action bindings: {}", err);
+        ActionMap::new()
+    });
+    app.ecs_mut().insert_resource(actions);
 
+    app.run();
 }
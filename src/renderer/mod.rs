@@ -2,7 +2,7 @@ mod render_pipeline;
 pub mod shader;
 
 use gfx_backend_vulkan as vulkan;
-use super::window::Window;
+use super::window::{self, Window};
 use render_pipeline::RenderPipeline;
 use std::rc::Rc;
 use std::mem::ManuallyDrop;
@@ -13,7 +13,7 @@ CommandPool, Adapter, SwapchainConfig, Swapchain, SurfaceCapabilities,
 command::{CommandBuffer, MultiShot, Primary, ClearValue, ClearColor},
 pool::{CommandPoolCreateFlags},
 format::{ChannelType, Format, Swizzle, Aspects},
-window::{Extent2D},
+window::{Extent2D, AcquireError, CreationError, PresentError, PresentMode},
 image::{ViewKind, SubresourceRange, Extent},
 pso::{Rect, PipelineStage},
 queue::Submission
@@ -21,11 +21,14 @@ queue::Submission
 use failure::Error;
 use arrayvec::ArrayVec;
 
+type Backbuffer = Vec<<vulkan::Backend as Backend>::Image>;
+
 pub struct Renderer<'a>{
     adapter : Adapter<vulkan::Backend>,
     device : ManuallyDrop<Rc<vulkan::Device>>,
     queue_group : QueueGroup<vulkan::Backend, Graphics>,
-    swapchain : ManuallyDrop<<vulkan::Backend as Backend>::Swapchain>,
+    //None after recreating it failed, it is tried again next frame
+    swapchain : Option<<vulkan::Backend as Backend>::Swapchain>,
     render_pipeline : ManuallyDrop<RenderPipeline<'a>>,
     command_pool : ManuallyDrop<CommandPool<vulkan::Backend, Graphics>>,
     number_of_images : u8,
@@ -37,6 +40,12 @@ pub struct Renderer<'a>{
     image_views: Vec<<vulkan::Backend as Backend>::ImageView>,
     framebuffers : Vec<<vulkan::Backend as Backend>::Framebuffer>,
     surface : <vulkan::Backend as Backend>::Surface,
+    format : Format,
+    //size of the window in pixels, used when the surface doesn't decide the swapchain size itself
+    extent : Extent2D,
+    recreate_swapchain : bool,
+    //the supported mode closest to the one the window config asked for
+    present_mode : PresentMode,
     render_area : Rect,
     instance : ManuallyDrop<vulkan::Instance>,
 }
@@ -49,7 +58,7 @@ impl<'a> Renderer<'a>{
         let adapter = instance.enumerate_adapters()
                             .pop()
                             .expect("[ERROR] Couldn't find a graphics adapter");
-        println!("[INFO] Using graphics adapter: {:?}", adapter.info.name);
+        log::info!("Using graphics adapter: {:?}", adapter.info.name);
 
 
         let (device, queue_group) = {
@@ -65,21 +74,20 @@ impl<'a> Renderer<'a>{
             )
         }.expect("[ERROR] failed to create command pool");
 
-        let (caps, formats, _present_modes) = surface.compatibility(&adapter.physical_device);
+        let (caps, formats, present_modes) = surface.compatibility(&adapter.physical_device);
         let format = Self::get_format(&formats);
+        let present_mode = Self::get_present_mode(&present_modes, win.config().present_mode);
         let render_pipeline = RenderPipeline::new(Rc::downgrade(&device), format);
-        let (swapchain, backbuffer, number_of_images, extent) = unsafe { Self::make_swapchain(&device, &mut surface , format, &caps) };
+        let (width, height) = win.physical_size();
+        let (swapchain, backbuffer, extent) = unsafe { Self::make_swapchain(&device, &mut surface , format, present_mode, &caps, Extent2D { width, height }) }
+                                                .expect("[ERROR] Couldn't create the swapchain");
+        let number_of_images = backbuffer.len() as u8;
         let (image_ready_semaphores, render_finished_semaphores, fences) = Self::make_synchronization_types(&device, number_of_images.into()).unwrap();
         let image_views = unsafe { Self::make_image_views(&device, &backbuffer, format).unwrap() };
         let framebuffers = unsafe {Self::make_framebuffers(&device, &image_views, &render_pipeline.render_pass, &extent.to_extent()).unwrap() };
         let command_buffers: Vec<_> = framebuffers.iter().map(|_| command_pool.acquire_command_buffer()).collect();
         let current_frame : usize = 0;
-        let render_area = Rect {
-            x : 0,
-            y : 0,
-            w : extent.width as i16,
-            h : extent.height as i16
-        };
+        let render_area = Self::make_render_area(extent);
 
         Renderer{
             instance : ManuallyDrop::new(instance),
@@ -87,7 +95,7 @@ impl<'a> Renderer<'a>{
             adapter,
             device : ManuallyDrop::new(device),
             queue_group,
-            swapchain : ManuallyDrop::new(swapchain),
+            swapchain : Some(swapchain),
             render_pipeline : ManuallyDrop::new(render_pipeline),
             command_pool: ManuallyDrop::new(command_pool),
             number_of_images,
@@ -98,6 +106,10 @@ impl<'a> Renderer<'a>{
             command_buffers,
             image_views,
             framebuffers,
+            format,
+            extent,
+            recreate_swapchain : false,
+            present_mode,
             render_area
         }
     }
@@ -116,25 +128,111 @@ impl<'a> Renderer<'a>{
         }
     }
 
-    unsafe fn make_swapchain(device : &vulkan::Device, surface : &mut <vulkan::Backend as Backend>::Surface, format : Format, caps : &SurfaceCapabilities)
-        -> (<vulkan::Backend as Backend>::Swapchain,
-            Vec<<vulkan::Backend as Backend>::Image>,
-            u8,
-            Extent2D)
+    //Fifo is the only mode every surface has to support, so it ends every list
+    fn get_present_mode(present_modes : &[PresentMode], preferred : window::PresentMode) -> PresentMode {
+        let fallbacks : &[PresentMode] = match preferred {
+            window::PresentMode::Immediate => &[PresentMode::Immediate, PresentMode::Mailbox, PresentMode::Fifo],
+            window::PresentMode::Mailbox => &[PresentMode::Mailbox, PresentMode::Fifo],
+            window::PresentMode::Fifo => &[PresentMode::Fifo]
+        };
+        let present_mode = fallbacks.iter()
+            .find(|mode| present_modes.contains(mode))
+            .cloned()
+            .unwrap_or(PresentMode::Fifo);
+        if present_mode != fallbacks[0] {
+            log::warn!("Present mode {:?} isn't supported, using {:?}", fallbacks[0], present_mode);
+        }
+        present_mode
+    }
+
+    fn make_swapchain_config(caps : &SurfaceCapabilities, format : Format, present_mode : PresentMode, window_extent : Extent2D) -> SwapchainConfig {
+        let mut config = SwapchainConfig::from_caps(caps, format, window_extent);
+        config.present_mode = present_mode;
+        //mailbox needs a spare image to replace, otherwise it waits like fifo
+        if present_mode == PresentMode::Mailbox && caps.image_count.end > 3 {
+            config.image_count = config.image_count.max(3);
+        }
+        config
+    }
+
+    //window_extent is only used if the surface doesn't dictate the size
+    unsafe fn make_swapchain(device : &vulkan::Device, surface : &mut <vulkan::Backend as Backend>::Surface, format : Format, present_mode : PresentMode, caps : &SurfaceCapabilities, window_extent : Extent2D)
+        -> Result<(<vulkan::Backend as Backend>::Swapchain, Backbuffer, Extent2D), Error>
         {
-        let config = SwapchainConfig::from_caps(caps, format, Extent2D {width:1024, height:768});
-        // let config = SwapchainConfig {
-        //     present_mode : gfx_hal::window::PresentMode::Mailbox,
-        //     composite_alpha : gfx_hal::window::CompositeAlpha::Opaque,
-        //     format,
-        //     extent : caps.extents.end,
-        //     image_count : 3u32,
-        //     image_layers : 1u16,
-        //     image_usage : gfx_hal::image::Usage::COLOR_ATTACHMENT
-        // };
-
-        let (swapchain, backbuffer) = device.create_swapchain(surface, config, None).unwrap();
-        (swapchain, backbuffer, 3, caps.extents.end)
+        let config = Self::make_swapchain_config(caps, format, present_mode, window_extent);
+
+        let extent = config.extent;
+        let (swapchain, backbuffer) = device.create_swapchain(surface, config, None)?;
+        Ok((swapchain, backbuffer, extent))
+    }
+
+    fn make_render_area(extent : Extent2D) -> Rect {
+        Rect {
+            x : 0,
+            y : 0,
+            w : extent.width as i16,
+            h : extent.height as i16
+        }
+    }
+
+    //Call when the window changed size, the swapchain is recreated before the next frame is drawn.
+    //Width and height are in physical pixels.
+    pub fn resize(&mut self, width : u32, height : u32) {
+        self.extent = Extent2D { width, height };
+        self.recreate_swapchain = true;
+    }
+
+    //Builds the swapchain and everything made from its images again, after a resize or when the swapchain
+    //was reported out of date. Returns false when the frame has to be skipped: while the window has no area,
+    //e.g. minimized, or when creating the swapchain failed in a way that may pass. recreate_swapchain stays set then.
+    unsafe fn recreate_swapchain(&mut self) -> Result<bool, Error> {
+        let (caps, _, _) = self.surface.compatibility(&self.adapter.physical_device);
+        let config = Self::make_swapchain_config(&caps, self.format, self.present_mode, self.extent);
+        let extent = config.extent;
+        if extent.width == 0 || extent.height == 0 {
+            return Ok(false);
+        }
+
+        self.device.wait_idle()?;
+        for framebuffer in self.framebuffers.drain(..) {
+            self.device.destroy_framebuffer(framebuffer);
+        }
+        for view in self.image_views.drain(..) {
+            self.device.destroy_image_view(view);
+        }
+
+        //the old swapchain is retired by creating the new one, even if that fails.
+        //Running out of memory or another API holding the window can pass, the frame is skipped and it is tried
+        //again next frame. A lost device or surface can't be recovered from here.
+        let (swapchain, backbuffer) = match self.device.create_swapchain(&mut self.surface, config, self.swapchain.take()) {
+            Ok(created) => created,
+            Err(err @ CreationError::OutOfMemory(_)) | Err(err @ CreationError::WindowInUse(_)) => {
+                log::warn!("Couldn't recreate the swapchain, skipping the frame: {}", err);
+                return Ok(false);
+            },
+            Err(err) => return Err(err.into())
+        };
+        self.swapchain = Some(swapchain);
+
+        if backbuffer.len() != self.number_of_images as usize {
+            self.destroy_synchronization_types();
+            let (image_ready_semaphores, render_finished_semaphores, fences) = Self::make_synchronization_types(&self.device, backbuffer.len() as u32)?;
+            self.image_ready_semaphores = image_ready_semaphores;
+            self.render_finished_semaphores = render_finished_semaphores;
+            self.fences = fences;
+            self.command_pool.free(self.command_buffers.drain(..));
+            for _ in 0..backbuffer.len() {
+                self.command_buffers.push(self.command_pool.acquire_command_buffer());
+            }
+            self.number_of_images = backbuffer.len() as u8;
+            self.current_frame = 0;
+        }
+
+        self.image_views = Self::make_image_views(&self.device, &backbuffer, self.format)?;
+        self.framebuffers = Self::make_framebuffers(&self.device, &self.image_views, &self.render_pipeline.render_pass, &extent.to_extent())?;
+        self.render_area = Self::make_render_area(extent);
+        self.recreate_swapchain = false;
+        Ok(true)
     }
 
     fn make_synchronization_types(device : &vulkan::Device, amount : u32) -> Result<(Vec<<vulkan::Backend as Backend>::Semaphore>, Vec<<vulkan::Backend as Backend>::Semaphore>, Vec<<vulkan::Backend as Backend>::Fence>), Error> {
@@ -149,6 +247,18 @@ impl<'a> Renderer<'a>{
         Ok((image_ready_semaphores, render_finished_semaphores, fences))
     }
 
+    unsafe fn destroy_synchronization_types(&mut self) {
+        for fence in self.fences.drain(..) {
+            self.device.destroy_fence(fence)
+        }
+        for semaphore in self.render_finished_semaphores.drain(..) {
+            self.device.destroy_semaphore(semaphore);
+        }
+        for semaphore in self.image_ready_semaphores.drain(..) {
+            self.device.destroy_semaphore(semaphore);
+        }
+    }
+
     unsafe fn make_image_views(device : &vulkan::Device, backbuffer : &Vec<<vulkan::Backend as Backend>::Image>, format : Format) -> Result<Vec<<vulkan::Backend as Backend>::ImageView>, Error> {
         Ok(backbuffer.into_iter()
                 .map(| image | {
@@ -185,19 +295,31 @@ impl<'a> Renderer<'a>{
         )
     }
 
+    //Skips the frame while the window is minimized or the swapchain couldn't be recreated yet
     pub fn draw_clear_colour(&mut self, colour : [f32; 4]) -> Result<(), Error>{
+        if self.recreate_swapchain && !unsafe { self.recreate_swapchain()? } {
+            return Ok(());
+        }
+
         let image_available = &self.image_ready_semaphores[self.current_frame];
         let render_finished = &self.render_finished_semaphores[self.current_frame];
-        self.current_frame = (self.current_frame + 1) % self.number_of_images as usize;
+        let swapchain = self.swapchain.as_mut().expect("swapchain is only missing until it is recreated");
 
         let i_usize = unsafe {
-            let image_index = self.swapchain
-                .acquire_image(!0, Some(image_available), None)
-                .map_err(|_| failure::err_msg("Couldn't acquire an image from the swapchain!"))?;
-            match image_index {
-                (i, _) => i as usize
+            match swapchain.acquire_image(!0, Some(image_available), None) {
+                //a suboptimal image can still be presented, the swapchain is recreated after
+                Ok((i, suboptimal)) => {
+                    self.recreate_swapchain |= suboptimal.is_some();
+                    i as usize
+                },
+                Err(AcquireError::OutOfDate) => {
+                    self.recreate_swapchain = true;
+                    return Ok(());
+                },
+                Err(_) => return Err(failure::err_msg("Couldn't acquire an image from the swapchain!"))
             }
         };
+        self.current_frame = (self.current_frame + 1) % self.number_of_images as usize;
 
         let fence = &self.fences[i_usize];
         unsafe {
@@ -239,10 +361,11 @@ impl<'a> Renderer<'a>{
     let the_command_queue = &mut self.queue_group.queues[0];
     unsafe {
       the_command_queue.submit(submission, Some(fence));
-      self
-        .swapchain
-        .present(the_command_queue, i_usize as u32, present_wait_semaphores)
-        .map_err(|_| failure::err_msg("Failed to present into the swapchain!"))?;
+      match swapchain.present(the_command_queue, i_usize as u32, present_wait_semaphores) {
+        Ok(None) => (),
+        Ok(Some(_)) | Err(PresentError::OutOfDate) => self.recreate_swapchain = true,
+        Err(_) => return Err(failure::err_msg("Failed to present into the swapchain!"))
+      }
     }
         Ok(())
     }
@@ -252,15 +375,7 @@ impl<'a> Drop for Renderer<'a> {
     fn drop(&mut self) {
         let _ = self.device.wait_idle();
         unsafe{
-        for fence in self.fences.drain(..) {
-            self.device.destroy_fence(fence)
-        }
-        for semaphore in self.render_finished_semaphores.drain(..) {
-            self.device.destroy_semaphore(semaphore);
-        }
-        for semaphore in self.image_ready_semaphores.drain(..) {
-            self.device.destroy_semaphore(semaphore);
-        }
+        self.destroy_synchronization_types();
         for buffer in self.framebuffers.drain(..) {
             self.device.destroy_framebuffer(buffer);
         }
@@ -269,7 +384,9 @@ impl<'a> Drop for Renderer<'a> {
         }
             self.device.destroy_command_pool(ManuallyDrop::into_inner(read(&mut self.command_pool)).into_raw());
             ManuallyDrop::drop(&mut self.render_pipeline);
-            self.device.destroy_swapchain(ManuallyDrop::into_inner(read(&mut self.swapchain)));
+            if let Some(swapchain) = self.swapchain.take() {
+                self.device.destroy_swapchain(swapchain);
+            }
 
             ManuallyDrop::drop(&mut self.device);
             ManuallyDrop::drop(&mut self.instance);
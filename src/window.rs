@@ -1,35 +1,273 @@
-
 extern crate winit;
 
-use winit::{EventsLoop, Event, WindowEvent};
+use std::fs;
+use failure::Error;
+use serde::{Deserialize, Serialize};
+use winit::{EventsLoop, Event, WindowEvent, Icon, MonitorId};
+use winit::dpi::{LogicalSize, PhysicalSize};
+use crate::ecs::events::Events;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Fullscreen {
+    Windowed,
+    //a window without decorations covering the whole monitor
+    Borderless,
+    Exclusive
+}
+
+//How the Renderer presents frames. When the surface doesn't support a mode it falls back towards Fifo,
+//syncing more rather than tearing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PresentMode {
+    //vsync, always supported
+    Fifo,
+    //vsync, but newer frames replace ones that are still waiting
+    Mailbox,
+    //no vsync, may tear
+    Immediate
+}
+
+//Everything the window is created with, usually loaded from a settings file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowConfig {
+    pub title : String,
+    //logical pixels, scaled by the DPI factor of the monitor unless scale_with_dpi is off
+    pub size : (f64, f64),
+    pub min_size : Option<(f64, f64)>,
+    pub max_size : Option<(f64, f64)>,
+    //false takes the sizes as physical pixels, e.g. to get a window exactly as big as a fixed render resolution.
+    //They are converted with the factor of the monitor the window is created on.
+    pub scale_with_dpi : bool,
+    pub fullscreen : Fullscreen,
+    //index into the available monitors, the primary monitor if None or out of range
+    pub monitor : Option<usize>,
+    pub resizable : bool,
+    //path to an image file
+    pub icon : Option<String>,
+    pub cursor_grab : bool,
+    pub cursor_visible : bool,
+    pub present_mode : PresentMode
+}
+
+impl Default for WindowConfig {
+    fn default() -> WindowConfig {
+        WindowConfig {
+            title : "window".to_string(),
+            size : (1024.0, 768.0),
+            min_size : None,
+            max_size : None,
+            scale_with_dpi : true,
+            fullscreen : Fullscreen::Windowed,
+            monitor : None,
+            resizable : true,
+            icon : None,
+            cursor_grab : false,
+            cursor_visible : true,
+            present_mode : PresentMode::Fifo
+        }
+    }
+}
+
+impl WindowConfig {
+    pub fn new(title : &str) -> WindowConfig {
+        WindowConfig {
+            title : title.to_string(),
+            ..WindowConfig::default()
+        }
+    }
+
+    pub fn load(path : &str) -> Result<WindowConfig, Error> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path : &str) -> Result<(), Error> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn with_size(mut self, width : f64, height : f64) -> WindowConfig {
+        self.size = (width, height);
+        self
+    }
+
+    pub fn with_min_size(mut self, width : f64, height : f64) -> WindowConfig {
+        self.min_size = Some((width, height));
+        self
+    }
+
+    pub fn with_max_size(mut self, width : f64, height : f64) -> WindowConfig {
+        self.max_size = Some((width, height));
+        self
+    }
+
+    pub fn with_scale_with_dpi(mut self, scale : bool) -> WindowConfig {
+        self.scale_with_dpi = scale;
+        self
+    }
+
+    pub fn with_fullscreen(mut self, fullscreen : Fullscreen) -> WindowConfig {
+        self.fullscreen = fullscreen;
+        self
+    }
+
+    pub fn with_monitor(mut self, monitor : usize) -> WindowConfig {
+        self.monitor = Some(monitor);
+        self
+    }
+
+    pub fn with_resizable(mut self, resizable : bool) -> WindowConfig {
+        self.resizable = resizable;
+        self
+    }
+
+    pub fn with_icon(mut self, path : &str) -> WindowConfig {
+        self.icon = Some(path.to_string());
+        self
+    }
+
+    pub fn with_cursor_grab(mut self, grab : bool) -> WindowConfig {
+        self.cursor_grab = grab;
+        self
+    }
+
+    pub fn with_cursor_visible(mut self, visible : bool) -> WindowConfig {
+        self.cursor_visible = visible;
+        self
+    }
+
+    pub fn with_present_mode(mut self, present_mode : PresentMode) -> WindowConfig {
+        self.present_mode = present_mode;
+        self
+    }
+}
+
+fn load_icon(path : &str) -> Result<Icon, Error> {
+    let image = image::open(path)?.to_rgba();
+    let (width, height) = image.dimensions();
+    Ok(Icon::from_rgba(image.into_raw(), width, height)?)
+}
 
 pub struct Window {
     pub window : winit::Window,
     events_loop : EventsLoop,
+    config : WindowConfig,
+    resized : bool,
 }
 
 impl Window {
-    pub fn new(title : &str) -> Window {
+    pub fn new(config : WindowConfig) -> Window {
         let events_loop = winit::EventsLoop::new();
-        let window = winit::WindowBuilder::new()
-            .with_title(title)
+        let monitor : MonitorId = config.monitor
+            .and_then(|i| events_loop.get_available_monitors().nth(i))
+            .unwrap_or_else(|| events_loop.get_primary_monitor());
+
+        let dpi_factor = monitor.get_hidpi_factor();
+        let scale_with_dpi = config.scale_with_dpi;
+        let logical = |size : (f64, f64)| if scale_with_dpi {
+            LogicalSize::from(size)
+        } else {
+            PhysicalSize::from(size).to_logical(dpi_factor)
+        };
+
+        let mut builder = winit::WindowBuilder::new()
+            .with_title(config.title.clone())
+            .with_dimensions(logical(config.size))
+            .with_resizable(config.resizable);
+        if let Some(size) = config.min_size {
+            builder = builder.with_min_dimensions(logical(size));
+        }
+        if let Some(size) = config.max_size {
+            builder = builder.with_max_dimensions(logical(size));
+        }
+        if let Some(path) = &config.icon {
+            match load_icon(path) {
+                Ok(icon) => builder = builder.with_window_icon(Some(icon)),
+                Err(err) => log::warn!("Couldn't load window icon {}: {}", path, err)
+            }
+        }
+        match config.fullscreen {
+            Fullscreen::Windowed => (),
+            //winit can't change the video mode, so this uses the monitor's current one
+            Fullscreen::Exclusive => builder = builder.with_fullscreen(Some(monitor.clone())),
+            Fullscreen::Borderless => {
+                let size = monitor.get_dimensions().to_logical(monitor.get_hidpi_factor());
+                builder = builder.with_decorations(false).with_dimensions(size);
+            }
+        }
+
+        let window = builder
             .build(&events_loop)
             .expect("Could not create window");
-        ;
-        Window {
+        if config.fullscreen == Fullscreen::Borderless {
+            window.set_position(monitor.get_position().to_logical(monitor.get_hidpi_factor()));
+        }
+
+        let (grab, visible) = (config.cursor_grab, config.cursor_visible);
+        let mut window = Window {
             window,
             events_loop,
+            config,
+            resized : false,
+        };
+        if grab {
+            window.set_cursor_grab(true);
+        }
+        if !visible {
+            window.set_cursor_visible(false);
         }
+        window
+    }
+
+    //What the window was created with, cursor settings follow later changes
+    pub fn config(&self) -> &WindowConfig {
+        &self.config
     }
 
-    pub fn poll_events(&mut self) -> bool {
+    //Keeps the cursor inside the window, not every platform supports it
+    pub fn set_cursor_grab(&mut self, grab : bool) {
+        self.config.cursor_grab = grab;
+        if let Err(err) = self.window.grab_cursor(grab) {
+            log::warn!("Couldn't grab the cursor: {}", err);
+        }
+    }
+
+    pub fn set_cursor_visible(&mut self, visible : bool) {
+        self.config.cursor_visible = visible;
+        self.window.hide_cursor(!visible);
+    }
+
+    //Size of the drawable area in pixels, 0 by 0 while minimized
+    pub fn physical_size(&self) -> (u32, u32) {
+        match self.window.get_inner_size() {
+            Some(size) => {
+                let size = size.to_physical(self.window.get_hidpi_factor());
+                (size.width.round() as u32, size.height.round() as u32)
+            },
+            None => (0, 0)
+        }
+    }
+
+    //True once after every poll_events in which the window changed size
+    pub fn take_resized(&mut self) -> bool {
+        std::mem::replace(&mut self.resized, false)
+    }
+
+    //Every winit event is also sent to events, returns false once the window is asked to close
+    pub fn poll_events(&mut self, events : &mut Events<Event>) -> bool {
         let mut running = true;
+        let resized = &mut self.resized;
         self.events_loop.poll_events(| event | {
-            match event {
+            match &event {
                 Event::WindowEvent { event:win_event , ..} => {
                     match win_event {
                         WindowEvent::Resized(winit::dpi::LogicalSize{width, height}) => {
-                            println!("[INFO] Resize {}, {}", width, height);
+                            log::info!("Resize {}, {}", width, height);
+                            *resized = true;
+                        },
+
+                        WindowEvent::HiDpiFactorChanged(_) => {
+                            *resized = true;
                         },
 
                         WindowEvent::CloseRequested => {
@@ -42,6 +280,7 @@ impl Window {
 
                 _ => ()
             }
+            events.send(event);
         });
 
         running